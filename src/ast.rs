@@ -3,7 +3,7 @@ pub enum RegexNode {
     Literal(char),
     CharacterClass {
         negated: bool,
-        chars: Vec<char>,
+        items: Vec<ClassItem>,
     },
     Dot,
     Anchor(AnchorType),
@@ -20,15 +20,44 @@ pub enum RegexNode {
         category: UnicodeCategoryKind,
     },
     Alternation(Vec<Vec<RegexNode>>),
-    Lookaround(LookaroundKind, Box<Vec<RegexNode>>),
-    FlagSet(RegexFlags, Box<Vec<RegexNode>>),
+    Lookaround(LookaroundKind, Vec<RegexNode>),
+    FlagSet(FlagChange, FlagScope, Vec<RegexNode>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClassItem {
+    Char(char),                         // a
+    Range { start: char, end: char },   // a-z (inclusive)
+    Escaped(EscapedChar),               // \n, \x41, \u{263A}
+    CharacterType(CharacterTypeKind),   // \d, \w, \s and their negations
+    UnicodeCategory {
+        negated: bool,
+        category: UnicodeCategoryKind,
+    },                                  // \p{L}, \P{N}
 }
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct RegexFlags {
-    pub case_insensitive: bool,
-    pub multiline: bool,
-    pub dot_all: bool,
+    pub case_insensitive: bool, // i
+    pub multiline: bool,        // m
+    pub dot_all: bool,          // s
+    pub extended: bool,         // x, whitespace and `#` comments are ignored
+    pub swap_greed: bool,       // U, quantifiers are lazy unless followed by `?`
+    pub unicode: bool,          // u, unicode case folding, which matching here always uses
+    pub no_auto_capture: bool,  // n, only named groups capture
+}
+
+/// The flags a `(?i-s)` or `(?i-s:...)` group turns on, and those it turns off.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FlagChange {
+    pub set: RegexFlags,
+    pub clear: RegexFlags,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlagScope {
+    Scoped,   // (?i:...)
+    Unscoped, // (?i)... up to the next `|` or the end of the enclosing group
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -50,8 +79,9 @@ pub enum EscapedChar {
     FormFeed,       // \f
     VerticalTab,    // \v
     Null,           // \0
-    Hex(u32),      // \xHH
-    Unicode(u32),   // \u{H...}
+    Hex(u32),       // \xHH, or \x{H...} where supported
+    Unicode(u32),   // \u{H...}, or \uHHHH where supported
+    Octal(u32),     // \0oo, or \o{o...} where supported
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -70,6 +100,7 @@ pub enum UnicodeCategoryKind {
 pub enum GroupKind {
     Capturing(Option<String>), // None for unnamed, Some(name) for named groups
     NonCapturing,
+    Atomic,                    // (?>...), which never backtracks into its body
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -86,12 +117,20 @@ pub enum AnchorType {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Quantifier {
-    ZeroOrMore { lazy: bool },     // * or *?
-    OneOrMore { lazy: bool },      // + or +?
-    ZeroOrOne { lazy: bool },      // ? or ??
-    Exactly(usize),                // {n}
-    AtLeast(usize),                // {n,}
-    Range { min: usize, max: usize }, // {n,m}
+    ZeroOrMore { mode: QuantifierMode },     // *, *? or *+
+    OneOrMore { mode: QuantifierMode },      // +, +? or ++
+    ZeroOrOne { mode: QuantifierMode },      // ?, ?? or ?+
+    Exactly(usize),                          // {n}
+    AtLeast { min: usize, mode: QuantifierMode },             // {n,}, {n,}? or {n,}+
+    Range { min: usize, max: usize, mode: QuantifierMode },   // {n,m}, {n,m}? or {n,m}+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuantifierMode {
+    #[default]
+    Greedy,     // as many as possible, giving back on backtracking
+    Lazy,       // as few as possible, taking more on backtracking
+    Possessive, // as many as possible, never giving any back
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -108,7 +147,11 @@ impl RegexNode {
     }
 
     pub fn new_char_class(chars: Vec<char>, negated: bool) -> Self {
-        RegexNode::CharacterClass { chars, negated }
+        RegexNode::new_class(chars.into_iter().map(ClassItem::Char).collect(), negated)
+    }
+
+    pub fn new_class(items: Vec<ClassItem>, negated: bool) -> Self {
+        RegexNode::CharacterClass { items, negated }
     }
 
     pub fn new_anchor(anchor_type: AnchorType) -> Self {
@@ -143,11 +186,127 @@ impl RegexNode {
     }
 
     pub fn new_lookaround(kind: LookaroundKind, nodes: Vec<RegexNode>) -> Self {
-        RegexNode::Lookaround(kind, Box::new(nodes))
+        RegexNode::Lookaround(kind, nodes)
+    }
+
+    pub fn new_flag_set(change: impl Into<FlagChange>, nodes: Vec<RegexNode>) -> Self {
+        RegexNode::FlagSet(change.into(), FlagScope::Unscoped, nodes)
+    }
+
+    pub fn new_scoped_flag_set(change: impl Into<FlagChange>, nodes: Vec<RegexNode>) -> Self {
+        RegexNode::FlagSet(change.into(), FlagScope::Scoped, nodes)
+    }
+
+    /// Whether this node contains a capturing group anywhere inside it.
+    /// Such nodes cannot be duplicated without shifting group numbers.
+    pub fn has_capturing_group(&self) -> bool {
+        let any = |nodes: &[RegexNode]| nodes.iter().any(|node| node.has_capturing_group());
+        match self {
+            RegexNode::Group(GroupKind::Capturing(_), _) => true,
+            RegexNode::Group(_, nodes)
+            | RegexNode::Lookaround(_, nodes)
+            | RegexNode::FlagSet(_, _, nodes) => any(nodes),
+            RegexNode::Quantified { node, .. } => node.has_capturing_group(),
+            RegexNode::Alternation(alternatives) => alternatives.iter().any(|alt| any(alt)),
+            _ => false,
+        }
+    }
+}
+
+/// Visits every node of the tree, each parent before its children.
+pub fn walk<'a>(nodes: &'a [RegexNode], f: &mut impl FnMut(&'a RegexNode)) {
+    for node in nodes {
+        f(node);
+        match node {
+            RegexNode::Quantified { node, .. } => walk(std::slice::from_ref(node.as_ref()), f),
+            RegexNode::Group(_, nodes)
+            | RegexNode::Lookaround(_, nodes)
+            | RegexNode::FlagSet(_, _, nodes) => walk(nodes, f),
+            RegexNode::Alternation(alternatives) => {
+                for alt in alternatives {
+                    walk(alt, f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Like [`walk`], but allows each node to be changed in place. Children are
+/// visited after their parent has been changed.
+pub fn walk_mut(nodes: &mut [RegexNode], f: &mut impl FnMut(&mut RegexNode)) {
+    for node in nodes {
+        f(node);
+        match node {
+            RegexNode::Quantified { node, .. } => walk_mut(std::slice::from_mut(node.as_mut()), f),
+            RegexNode::Group(_, nodes)
+            | RegexNode::Lookaround(_, nodes)
+            | RegexNode::FlagSet(_, _, nodes) => walk_mut(nodes, f),
+            RegexNode::Alternation(alternatives) => {
+                for alt in alternatives {
+                    walk_mut(alt, f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Quantifier {
+    /// How the quantifier repeats. `{n}` has only one way to, so it counts
+    /// as greedy.
+    pub fn mode(&self) -> QuantifierMode {
+        match *self {
+            Quantifier::ZeroOrMore { mode }
+            | Quantifier::OneOrMore { mode }
+            | Quantifier::ZeroOrOne { mode }
+            | Quantifier::AtLeast { mode, .. }
+            | Quantifier::Range { mode, .. } => mode,
+            Quantifier::Exactly(_) => QuantifierMode::Greedy,
+        }
     }
 
-    pub fn new_flag_set(flags: RegexFlags, nodes: Vec<RegexNode>) -> Self {
-        RegexNode::FlagSet(flags, Box::new(nodes))
+    /// The same repetition count with a different mode. `{n}` is unchanged.
+    pub fn with_mode(self, mode: QuantifierMode) -> Self {
+        match self {
+            Quantifier::ZeroOrMore { .. } => Quantifier::ZeroOrMore { mode },
+            Quantifier::OneOrMore { .. } => Quantifier::OneOrMore { mode },
+            Quantifier::ZeroOrOne { .. } => Quantifier::ZeroOrOne { mode },
+            Quantifier::AtLeast { min, .. } => Quantifier::AtLeast { min, mode },
+            Quantifier::Range { min, max, .. } => Quantifier::Range { min, max, mode },
+            Quantifier::Exactly(n) => Quantifier::Exactly(n),
+        }
+    }
+}
+
+impl ClassItem {
+    pub fn new_range(start: char, end: char) -> Self {
+        ClassItem::Range { start, end }
+    }
+
+    /// Returns the single character this item stands for, if it is one.
+    pub fn as_char(&self) -> Option<char> {
+        match self {
+            ClassItem::Char(c) => Some(*c),
+            ClassItem::Escaped(esc) => esc.to_char(),
+            _ => None,
+        }
+    }
+}
+
+impl EscapedChar {
+    pub fn to_char(&self) -> Option<char> {
+        match self {
+            EscapedChar::Tab => Some('\t'),
+            EscapedChar::NewLine => Some('\n'),
+            EscapedChar::CarriageReturn => Some('\r'),
+            EscapedChar::FormFeed => Some('\u{0C}'),
+            EscapedChar::VerticalTab => Some('\u{0B}'),
+            EscapedChar::Null => Some('\0'),
+            EscapedChar::Hex(n) | EscapedChar::Unicode(n) | EscapedChar::Octal(n) => {
+                char::from_u32(*n)
+            }
+        }
     }
 }
 
@@ -162,16 +321,88 @@ impl RegexFlags {
             'i' => flags.case_insensitive = true,
             'm' => flags.multiline = true,
             's' => flags.dot_all = true,
+            'x' => flags.extended = true,
+            'U' => flags.swap_greed = true,
+            'u' => flags.unicode = true,
+            'n' => flags.no_auto_capture = true,
             _ => return None,
         }
         Some(flags)
     }
 
+    /// The letters of the flags that are on, in the order `imsxUun`.
+    pub fn to_chars(&self) -> Vec<char> {
+        let flags = [
+            ('i', self.case_insensitive),
+            ('m', self.multiline),
+            ('s', self.dot_all),
+            ('x', self.extended),
+            ('U', self.swap_greed),
+            ('u', self.unicode),
+            ('n', self.no_auto_capture),
+        ];
+        flags.into_iter().filter(|(_, on)| *on).map(|(c, _)| c).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == RegexFlags::new()
+    }
+
+    /// Turns on every flag that is on in `other`.
     pub fn merge(&self, other: &RegexFlags) -> RegexFlags {
+        self.combine(other, |this, other| this || other)
+    }
+
+    /// Turns off every flag that is on in `other`.
+    pub fn clear(&self, other: &RegexFlags) -> RegexFlags {
+        self.combine(other, |this, other| this && !other)
+    }
+
+    /// The flags in effect inside a group that makes `change` to these.
+    pub fn apply(&self, change: &FlagChange) -> RegexFlags {
+        self.merge(&change.set).clear(&change.clear)
+    }
+
+    fn combine(&self, other: &RegexFlags, f: impl Fn(bool, bool) -> bool) -> RegexFlags {
         RegexFlags {
-            case_insensitive: self.case_insensitive || other.case_insensitive,
-            multiline: self.multiline || other.multiline,
-            dot_all: self.dot_all || other.dot_all,
+            case_insensitive: f(self.case_insensitive, other.case_insensitive),
+            multiline: f(self.multiline, other.multiline),
+            dot_all: f(self.dot_all, other.dot_all),
+            extended: f(self.extended, other.extended),
+            swap_greed: f(self.swap_greed, other.swap_greed),
+            unicode: f(self.unicode, other.unicode),
+            no_auto_capture: f(self.no_auto_capture, other.no_auto_capture),
         }
     }
-} 
\ No newline at end of file
+}
+
+impl FlagChange {
+    /// The change that turns `from` into `to`.
+    pub fn between(from: &RegexFlags, to: &RegexFlags) -> FlagChange {
+        FlagChange {
+            set: to.clear(from),
+            clear: from.clear(to),
+        }
+    }
+
+    /// This change followed by `other`, which wins where both touch a flag.
+    pub fn then(&self, other: &FlagChange) -> FlagChange {
+        FlagChange {
+            set: self.set.clear(&other.clear).merge(&other.set),
+            clear: self.clear.clear(&other.set).merge(&other.clear),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.clear.is_empty()
+    }
+}
+
+impl From<RegexFlags> for FlagChange {
+    fn from(set: RegexFlags) -> Self {
+        FlagChange {
+            set,
+            clear: RegexFlags::new(),
+        }
+    }
+}
@@ -0,0 +1,440 @@
+//! Determinizes [`Nfa`]s by subset construction and decides whether two
+//! patterns denote the same language.
+//!
+//! The language of a pattern is the set of strings it matches in full, as
+//! if it were wrapped in `^(?:...)$`. Captures and match preferences play no
+//! part. Anchors and word boundaries are kept exact by tracking the kind of
+//! char each state was entered on.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use crate::ast::{CharacterTypeKind, ClassItem, GroupKind, Quantifier, QuantifierMode, RegexNode};
+use crate::nfa::{self, CharRange, CompileError, Nfa, State, StateId};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// A complete DFA over a partition of the chars into ranges. Every state
+/// has one transition per range; state 0 is the dead state.
+#[derive(Debug, Clone)]
+pub struct Dfa {
+    alphabet: Vec<CharRange>,
+    states: Vec<DfaState>,
+    start: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DfaState {
+    pub accepting: bool,
+    /// The next state for each range of the alphabet
+    pub transitions: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Equivalence {
+    Equivalent,
+    /// A shortest string that one pattern matches in full and the other
+    /// doesn't.
+    Different(String),
+}
+
+/// Decides whether two patterns from the regular subset match exactly the
+/// same strings.
+pub fn equivalent(a: &[RegexNode], b: &[RegexNode]) -> Result<Equivalence, CompileError> {
+    let (a, b) = (Nfa::compile(a)?, Nfa::compile(b)?);
+    let alphabet = alphabet(&[&a, &b]);
+    Ok(Dfa::from_nfa(&a, alphabet.clone()).compare(&Dfa::from_nfa(&b, alphabet)))
+}
+
+/// Splits the chars into ranges that every char test of the NFAs treats
+/// alike. Newlines and word chars get ranges of their own too, as anchors
+/// and word boundaries tell them apart.
+pub fn alphabet(nfas: &[&Nfa]) -> Vec<CharRange> {
+    let mut boundaries = vec!['\0'];
+    let mut add = |(start, end): CharRange| {
+        boundaries.push(start);
+        boundaries.extend(nfa::next_char(end));
+    };
+    for nfa in nfas {
+        for state in nfa.states() {
+            if let State::Chars { ranges, .. } = state {
+                ranges.iter().copied().for_each(&mut add);
+            }
+        }
+    }
+    add(('\n', '\n'));
+    nfa::word_ranges().into_iter().for_each(add);
+
+    boundaries.sort_unstable();
+    boundaries.dedup();
+    boundaries
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let end = match boundaries.get(i + 1) {
+                Some(&next) => nfa::previous_char(next).unwrap(),
+                None => char::MAX,
+            };
+            (start, end)
+        })
+        .collect()
+}
+
+// The kind of char a state was entered on, as far as assertions care. `None`
+// is the start of the input.
+type Context = Option<char>;
+
+fn context(c: char) -> Context {
+    if c == '\n' {
+        Some('\n')
+    } else if c.is_ascii_alphanumeric() || c == '_' {
+        Some('a')
+    } else {
+        Some('-')
+    }
+}
+
+impl Dfa {
+    /// Runs the subset construction. Each DFA state stands for the NFA states
+    /// reached before following empty transitions, along with the context,
+    /// since which assertions hold depends on the chars on both sides.
+    pub fn from_nfa(nfa: &Nfa, alphabet: Vec<CharRange>) -> Dfa {
+        // The dead state, then the start state
+        let mut keys: Vec<(Vec<StateId>, Context)> =
+            vec![(Vec::new(), None), (vec![nfa.start()], None)];
+        let mut ids: HashMap<(Vec<StateId>, Context), usize> =
+            keys.iter().cloned().zip(0..).collect();
+
+        let mut states = Vec::new();
+        let mut i = 0;
+        while i < keys.len() {
+            let (core, before) = keys[i].clone();
+            let accepting = closure(nfa, &core, before, None).contains(&StateId::MAX);
+
+            // Which states a char leads to only depends on the closure for its
+            // context and on which char states accept it
+            let mut closures: HashMap<Context, Vec<StateId>> = HashMap::new();
+            let mut transitions = Vec::with_capacity(alphabet.len());
+            for &(c, _) in &alphabet {
+                let reached = closures
+                    .entry(context(c))
+                    .or_insert_with(|| closure(nfa, &core, before, Some(c)));
+                let next: BTreeSet<StateId> = reached
+                    .iter()
+                    .filter_map(|&id| match nfa.states().get(id) {
+                        Some(State::Chars { ranges, next }) if nfa::in_ranges(ranges, c) => Some(*next),
+                        _ => None,
+                    })
+                    .collect();
+
+                let key = if next.is_empty() {
+                    (Vec::new(), None)
+                } else {
+                    (next.into_iter().collect(), context(c))
+                };
+                let id = *ids.entry(key.clone()).or_insert_with(|| {
+                    keys.push(key);
+                    keys.len() - 1
+                });
+                transitions.push(id);
+            }
+
+            states.push(DfaState { accepting, transitions });
+            i += 1;
+        }
+
+        Dfa { alphabet, states, start: 1 }
+    }
+
+    pub fn alphabet(&self) -> &[CharRange] {
+        &self.alphabet
+    }
+
+    pub fn states(&self) -> &[DfaState] {
+        &self.states
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Whether the DFA accepts the whole of `haystack`.
+    pub fn accepts(&self, haystack: &str) -> bool {
+        let mut state = self.start;
+        for c in haystack.chars() {
+            state = self.states[state].transitions[self.symbol(c)];
+        }
+        self.states[state].accepting
+    }
+
+    /// The index of the alphabet range `c` falls in.
+    pub fn symbol(&self, c: char) -> usize {
+        self.alphabet.partition_point(|&(start, _)| start <= c) - 1
+    }
+
+    /// Walks the product of both DFAs breadth first, so the first pair of
+    /// states that disagree is reached by a shortest string. Both must share
+    /// an alphabet.
+    pub fn compare(&self, other: &Dfa) -> Equivalence {
+        assert_eq!(self.alphabet, other.alphabet, "DFAs must share an alphabet");
+
+        type Pair = (usize, usize);
+
+        let start = (self.start, other.start);
+        // How each pair was first reached, as (previous pair, symbol)
+        let mut parents: HashMap<Pair, Option<(Pair, usize)>> = HashMap::new();
+        parents.insert(start, None);
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(pair @ (a, b)) = queue.pop_front() {
+            if self.states[a].accepting != other.states[b].accepting {
+                let mut chars = Vec::new();
+                let mut current = pair;
+                while let Some((previous, symbol)) = parents[&current] {
+                    chars.push(self.alphabet[symbol].0);
+                    current = previous;
+                }
+                return Equivalence::Different(chars.into_iter().rev().collect());
+            }
+
+            let transitions = self.states[a].transitions.iter().zip(&other.states[b].transitions);
+            for (symbol, (&next_a, &next_b)) in transitions.enumerate() {
+                let next = (next_a, next_b);
+                if let Entry::Vacant(entry) = parents.entry(next) {
+                    entry.insert(Some((pair, symbol)));
+                    queue.push_back(next);
+                }
+            }
+        }
+        Equivalence::Equivalent
+    }
+
+    /// Merges states that accept the same strings and drops unreachable
+    /// ones, by refining a partition until it stops changing. The dead state
+    /// stays at index 0.
+    pub fn minimize(&self) -> Dfa {
+        let mut reachable = vec![false; self.states.len()];
+        let mut stack = vec![0, self.start];
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut reachable[id], true) {
+                stack.extend(&self.states[id].transitions);
+            }
+        }
+        let ids: Vec<usize> = (0..self.states.len()).filter(|&id| reachable[id]).collect();
+
+        let mut class: Vec<usize> = self.states.iter().map(|state| state.accepting as usize).collect();
+        let mut class_count = 0;
+        loop {
+            let mut classes: HashMap<(usize, Vec<usize>), usize> = HashMap::new();
+            let mut refined = class.clone();
+            for &id in &ids {
+                let signature = (
+                    class[id],
+                    self.states[id].transitions.iter().map(|&next| class[next]).collect(),
+                );
+                let count = classes.len();
+                refined[id] = *classes.entry(signature).or_insert(count);
+            }
+            class = refined;
+            if classes.len() == class_count {
+                break;
+            }
+            class_count = classes.len();
+        }
+
+        let mut states = vec![None; class_count];
+        for &id in &ids {
+            states[class[id]].get_or_insert_with(|| DfaState {
+                accepting: self.states[id].accepting,
+                transitions: self.states[id].transitions.iter().map(|&next| class[next]).collect(),
+            });
+        }
+        Dfa {
+            alphabet: self.alphabet.clone(),
+            states: states.into_iter().map(Option::unwrap).collect(),
+            start: class[self.start],
+        }
+    }
+
+    /// Turns the DFA back into a pattern by state elimination: states are
+    /// removed one at a time in random order, each one's loops and paths
+    /// folded into the edges around it. The pattern is built from classes,
+    /// groups and quantifiers only, with no anchors or captures.
+    pub fn to_regex(&self, rng: &mut impl Rng) -> Vec<RegexNode> {
+        // States on some path from the start to an accepting state
+        let mut forward = vec![false; self.states.len()];
+        let mut stack = vec![self.start];
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut forward[id], true) {
+                stack.extend(&self.states[id].transitions);
+            }
+        }
+        let mut live: Vec<bool> = self.states.iter().map(|state| state.accepting).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (id, state) in self.states.iter().enumerate() {
+                if !live[id] && state.transitions.iter().any(|&next| live[next]) {
+                    live[id] = true;
+                    changed = true;
+                }
+            }
+        }
+        let ids: Vec<usize> = (0..self.states.len()).filter(|&id| forward[id] && live[id]).collect();
+        if ids.is_empty() {
+            return vec![never_matches()];
+        }
+
+        // The live states, then a new start and a new final state
+        let index: HashMap<usize, usize> = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+        let (start, end) = (ids.len(), ids.len() + 1);
+        let mut edges: Vec<Vec<Option<Vec<RegexNode>>>> = vec![vec![None; ids.len() + 2]; ids.len() + 2];
+        edges[start][index[&self.start]] = Some(Vec::new());
+        for (i, &id) in ids.iter().enumerate() {
+            if self.states[id].accepting {
+                edges[i][end] = Some(Vec::new());
+            }
+            let mut ranges: Vec<Vec<CharRange>> = vec![Vec::new(); ids.len()];
+            for (symbol, next) in self.states[id].transitions.iter().enumerate() {
+                if let Some(&j) = index.get(next) {
+                    ranges[j].push(self.alphabet[symbol]);
+                }
+            }
+            for (j, ranges) in ranges.into_iter().enumerate() {
+                if !ranges.is_empty() {
+                    edges[i][j] = Some(vec![class_of(nfa::normalize(ranges))]);
+                }
+            }
+        }
+
+        let mut order: Vec<usize> = (0..ids.len()).collect();
+        order.shuffle(rng);
+        let mut remaining: Vec<usize> = (0..ids.len() + 2).collect();
+        for k in order {
+            remaining.retain(|&i| i != k);
+            let looped = edges[k][k].take().map(star);
+            for &i in &remaining {
+                let Some(into) = edges[i][k].clone() else {
+                    continue;
+                };
+                for &j in &remaining {
+                    let Some(out) = edges[k][j].clone() else {
+                        continue;
+                    };
+                    let mut path = into.clone();
+                    path.extend(looped.iter().flatten().cloned());
+                    path.extend(out);
+                    edges[i][j] = Some(match edges[i][j].take() {
+                        Some(existing) => alternate(existing, path),
+                        None => path,
+                    });
+                }
+            }
+        }
+
+        edges[start][end].take().unwrap_or_else(|| vec![never_matches()])
+    }
+}
+
+// [^\s\S]
+fn never_matches() -> RegexNode {
+    RegexNode::new_class(
+        vec![
+            ClassItem::CharacterType(CharacterTypeKind::Whitespace),
+            ClassItem::CharacterType(CharacterTypeKind::NotWhitespace),
+        ],
+        true,
+    )
+}
+
+/// A single node for a set of chars: a literal if it is one char, otherwise
+/// whichever of the class and its negation needs fewer items.
+fn class_of(ranges: Vec<CharRange>) -> RegexNode {
+    if let [(start, end)] = ranges[..] {
+        if start == end {
+            return RegexNode::new_literal(start);
+        }
+    }
+    let items = |ranges: &[CharRange]| {
+        ranges
+            .iter()
+            .map(|&(start, end)| match start == end {
+                true => ClassItem::Char(start),
+                false => ClassItem::new_range(start, end),
+            })
+            .collect()
+    };
+    let complement = nfa::complement(&ranges);
+    if !complement.is_empty() && complement.len() < ranges.len() {
+        RegexNode::new_class(items(&complement), true)
+    } else {
+        RegexNode::new_class(items(&ranges), false)
+    }
+}
+
+/// Repeats a sequence any number of times.
+fn star(nodes: Vec<RegexNode>) -> Vec<RegexNode> {
+    if nodes.is_empty() {
+        return nodes;
+    }
+    vec![quantify(nodes, Quantifier::ZeroOrMore { mode: QuantifierMode::Greedy })]
+}
+
+fn quantify(nodes: Vec<RegexNode>, quantifier: Quantifier) -> RegexNode {
+    match <[RegexNode; 1]>::try_from(nodes) {
+        Ok([node @ (RegexNode::Literal(_) | RegexNode::CharacterClass { .. } | RegexNode::Group(..))]) => {
+            node.with_quantifier(quantifier)
+        }
+        Ok([node]) => RegexNode::new_group(GroupKind::NonCapturing, vec![node]).with_quantifier(quantifier),
+        Err(nodes) => RegexNode::new_group(GroupKind::NonCapturing, nodes).with_quantifier(quantifier),
+    }
+}
+
+/// `(?:a|b)`, merging with alternations already on either side. An empty side
+/// makes the other one optional instead, as `a|` can't be printed.
+fn alternate(a: Vec<RegexNode>, b: Vec<RegexNode>) -> Vec<RegexNode> {
+    match (a.is_empty(), b.is_empty()) {
+        (true, true) => return Vec::new(),
+        (true, false) => return vec![quantify(b, Quantifier::ZeroOrOne { mode: QuantifierMode::Greedy })],
+        (false, true) => return vec![quantify(a, Quantifier::ZeroOrOne { mode: QuantifierMode::Greedy })],
+        _ => {}
+    }
+    let alternatives = |nodes: Vec<RegexNode>| match <[RegexNode; 1]>::try_from(nodes) {
+        Ok([RegexNode::Group(GroupKind::NonCapturing, inner)]) => match <[RegexNode; 1]>::try_from(inner) {
+            Ok([RegexNode::Alternation(alternatives)]) => alternatives,
+            Ok([node]) => vec![vec![RegexNode::new_group(GroupKind::NonCapturing, vec![node])]],
+            Err(inner) => vec![vec![RegexNode::new_group(GroupKind::NonCapturing, inner)]],
+        },
+        Ok([node]) => vec![vec![node]],
+        Err(nodes) => vec![nodes],
+    };
+    let mut merged = alternatives(a);
+    merged.extend(alternatives(b));
+    vec![RegexNode::new_group(GroupKind::NonCapturing, vec![RegexNode::new_alternation(merged)])]
+}
+
+/// Follows empty transitions from `core`, checking assertions between the
+/// chars `before` and `after`. The result holds the char states reached, and
+/// `StateId::MAX` if the match state is among them.
+fn closure(nfa: &Nfa, core: &[StateId], before: Context, after: Option<char>) -> Vec<StateId> {
+    let mut reached = Vec::new();
+    let mut seen = vec![false; nfa.states().len()];
+    let mut stack: Vec<StateId> = core.iter().rev().copied().collect();
+    while let Some(id) = stack.pop() {
+        if std::mem::replace(&mut seen[id], true) {
+            continue;
+        }
+        match &nfa.states()[id] {
+            State::Chars { .. } => reached.push(id),
+            State::Match => reached.push(StateId::MAX),
+            State::Split(first, second) => stack.extend([*second, *first]),
+            State::Save { next, .. } => stack.push(*next),
+            State::Assert { assertion, next } => {
+                if nfa::assertion_holds_between(*assertion, before, after) {
+                    stack.push(*next);
+                }
+            }
+        }
+    }
+    reached
+}
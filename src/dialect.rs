@@ -0,0 +1,275 @@
+//! The regex engines patterns are read from, and what each of them supports.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ast::{self, GroupKind, RegexNode};
+use crate::matcher;
+
+/// A regex engine's syntax. `Generic` is the syntax the parser has always
+/// accepted: PCRE-style groups and lookarounds, with `\u{...}` escapes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    #[default]
+    Generic,
+    Pcre,
+    /// ECMAScript with the `u` flag, plus the inline modifiers of ES2025
+    JavaScript,
+    /// Python's `re` module
+    Python,
+    /// RE2, and Go's `regexp`, which follows it
+    Re2,
+    DotNet,
+    Java,
+    /// POSIX extended regular expressions, as used by `grep -E` and `awk`.
+    /// Bracket classes such as `[:alpha:]` are not read.
+    PosixEre,
+}
+
+/// A construct some dialects can't express.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Lookahead,
+    Lookbehind,
+    /// A lookbehind whose body can match strings of different lengths
+    VariableLengthLookbehind,
+    Backreference,
+    UnicodeCategory,
+    /// `\u` escapes; engines without them may still spell the char otherwise
+    UnicodeEscape,
+    /// `(?i)` applying to the rest of the enclosing group, rather than `(?i:...)`
+    UnscopedFlags,
+    InlineFlags,
+    NamedGroup,
+    NonCapturingGroup,
+    AtomicGroup,
+    LazyQuantifier,
+    PossessiveQuantifier,
+    /// `\d`, `\w`, `\s` and their negations
+    CharacterType,
+    WordBoundary,
+    /// Chars past U+FFFF inside a class, which .NET sees as two UTF-16 units
+    AstralCharInClass,
+}
+
+impl Dialect {
+    pub const ALL: [Dialect; 8] = [
+        Dialect::Generic,
+        Dialect::Pcre,
+        Dialect::JavaScript,
+        Dialect::Python,
+        Dialect::Re2,
+        Dialect::DotNet,
+        Dialect::Java,
+        Dialect::PosixEre,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dialect::Generic => "generic",
+            Dialect::Pcre => "pcre",
+            Dialect::JavaScript => "javascript",
+            Dialect::Python => "python",
+            Dialect::Re2 => "re2",
+            Dialect::DotNet => "dotnet",
+            Dialect::Java => "java",
+            Dialect::PosixEre => "ere",
+        }
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::Lookahead | Feature::Lookbehind | Feature::Backreference => {
+                !matches!(self, Dialect::Re2 | Dialect::PosixEre)
+            }
+            // Python only takes them at the very start of the pattern
+            Feature::UnscopedFlags => {
+                !matches!(self, Dialect::JavaScript | Dialect::Python | Dialect::PosixEre)
+            }
+            Feature::UnicodeCategory => !matches!(self, Dialect::Python | Dialect::PosixEre),
+            Feature::UnicodeEscape => {
+                !matches!(self, Dialect::Pcre | Dialect::Re2 | Dialect::PosixEre)
+            }
+            Feature::VariableLengthLookbehind => {
+                matches!(self, Dialect::Generic | Dialect::JavaScript | Dialect::DotNet)
+            }
+            Feature::InlineFlags
+            | Feature::NamedGroup
+            | Feature::NonCapturingGroup
+            | Feature::LazyQuantifier
+            | Feature::CharacterType
+            | Feature::WordBoundary => *self != Dialect::PosixEre,
+            Feature::AstralCharInClass => *self != Dialect::DotNet,
+            // Python has both since 3.11
+            Feature::AtomicGroup => {
+                !matches!(self, Dialect::JavaScript | Dialect::Re2 | Dialect::PosixEre)
+            }
+            Feature::PossessiveQuantifier => matches!(
+                self,
+                Dialect::Generic | Dialect::Pcre | Dialect::Python | Dialect::Java
+            ),
+        }
+    }
+
+    /// The letters `(?...)` takes, each as on or, after `-`, off. Java's `U`
+    /// is not the `U` of PCRE and RE2, so it is left out.
+    pub fn inline_flags(&self) -> &'static str {
+        match self {
+            Dialect::Generic => "imsxUun",
+            Dialect::Pcre => "imsxUn",
+            Dialect::JavaScript => "ims",
+            Dialect::Python => "imsxu",
+            Dialect::Re2 => "imsU",
+            Dialect::DotNet => "imsxn",
+            Dialect::Java => "imsxu",
+            Dialect::PosixEre => "",
+        }
+    }
+
+    /// The position of each capturing group among the groups of `nodes`,
+    /// listed in the order the dialect numbers them. .NET numbers unnamed
+    /// groups first and named ones after them; the others number groups as
+    /// they open.
+    pub fn group_order(&self, nodes: &[RegexNode]) -> Vec<usize> {
+        let mut unnamed = Vec::new();
+        let mut named = Vec::new();
+        ast::walk(nodes, &mut |node| {
+            if let RegexNode::Group(GroupKind::Capturing(name), _) = node {
+                let position = unnamed.len() + named.len() + 1;
+                match name {
+                    Some(_) if *self == Dialect::DotNet => named.push(position),
+                    _ => unnamed.push(position),
+                }
+            }
+        });
+        unnamed.extend(named);
+        unnamed
+    }
+
+    /// Checks a lookbehind body against the dialect's rules on its length:
+    /// Python wants a fixed length, PCRE a fixed length per top-level
+    /// alternative and Java a bounded one.
+    pub fn check_lookbehind(&self, body: &[RegexNode]) -> Result<(), Feature> {
+        if !self.supports(Feature::Lookbehind) {
+            return Err(Feature::Lookbehind);
+        }
+        let fixed = |nodes: &[RegexNode]| {
+            let (min, max) = width(nodes);
+            max == Some(min)
+        };
+        let allowed = match self {
+            Dialect::Python => fixed(body),
+            Dialect::Pcre => match body {
+                [RegexNode::Alternation(alternatives)] => alternatives.iter().all(|alt| fixed(alt)),
+                _ => fixed(body),
+            },
+            Dialect::Java => width(body).1.is_some(),
+            _ => true,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(Feature::VariableLengthLookbehind)
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Dialect::Generic => "the generic dialect",
+            Dialect::Pcre => "PCRE",
+            Dialect::JavaScript => "JavaScript",
+            Dialect::Python => "Python",
+            Dialect::Re2 => "RE2",
+            Dialect::DotNet => ".NET",
+            Dialect::Java => "Java",
+            Dialect::PosixEre => "POSIX ERE",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        let dialect = match s.as_str() {
+            "js" | "ecmascript" => Dialect::JavaScript,
+            "go" => Dialect::Re2,
+            ".net" | "net" | "csharp" => Dialect::DotNet,
+            "posix" | "posix-ere" => Dialect::PosixEre,
+            _ => *Dialect::ALL
+                .iter()
+                .find(|dialect| dialect.name() == s)
+                .ok_or_else(|| format!("unknown dialect `{}`", s))?,
+        };
+        Ok(dialect)
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Feature::Lookahead => "lookaheads",
+            Feature::Lookbehind => "lookbehinds",
+            Feature::VariableLengthLookbehind => "variable-length lookbehinds",
+            Feature::Backreference => "backreferences",
+            Feature::UnicodeCategory => "unicode categories",
+            Feature::UnicodeEscape => "`\\u` escapes",
+            Feature::UnscopedFlags => "flags that apply to the rest of the group",
+            Feature::InlineFlags => "inline flags",
+            Feature::NamedGroup => "named groups",
+            Feature::NonCapturingGroup => "non-capturing groups",
+            Feature::AtomicGroup => "atomic groups",
+            Feature::LazyQuantifier => "lazy quantifiers",
+            Feature::PossessiveQuantifier => "possessive quantifiers",
+            Feature::CharacterType => "`\\d`, `\\w` and `\\s` classes",
+            Feature::WordBoundary => "word boundaries",
+            Feature::AstralCharInClass => "chars past U+FFFF in classes",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The fewest and most chars a sequence can match, the most being `None`
+/// when unbounded or unknown, as with backreferences.
+pub(crate) fn width(nodes: &[RegexNode]) -> (usize, Option<usize>) {
+    nodes.iter().fold((0, Some(0)), |(min, max), node| {
+        let (node_min, node_max) = node_width(node);
+        // Past usize::MAX counts as unbounded
+        let max = max.zip(node_max).and_then(|(a, b)| a.checked_add(b));
+        (min.saturating_add(node_min), max)
+    })
+}
+
+pub(crate) fn node_width(node: &RegexNode) -> (usize, Option<usize>) {
+    match node {
+        RegexNode::Literal(_)
+        | RegexNode::CharacterClass { .. }
+        | RegexNode::Dot
+        | RegexNode::CharacterType(_)
+        | RegexNode::UnicodeCategory { .. } => (1, Some(1)),
+        RegexNode::Anchor(_) | RegexNode::WordBoundary | RegexNode::Lookaround(..) => (0, Some(0)),
+        RegexNode::Backreference(_) => (0, None),
+        RegexNode::Group(_, nodes) | RegexNode::FlagSet(_, _, nodes) => width(nodes),
+        RegexNode::Alternation(alternatives) => {
+            let widths: Vec<_> = alternatives.iter().map(|alt| width(alt)).collect();
+            let min = widths.iter().map(|(min, _)| *min).min().unwrap_or(0);
+            let max = widths.iter().try_fold(0, |max, (_, alt_max)| alt_max.map(|m| max.max(m)));
+            (min, max)
+        }
+        RegexNode::Quantified { node, quantifier } => {
+            let (min, max, _) = matcher::bounds(quantifier);
+            let (node_min, node_max) = node_width(node);
+            let max = match (max, node_max) {
+                // Repeating nothing stays nothing, however often
+                (_, Some(0)) | (Some(0), _) => Some(0),
+                (Some(max), Some(node_max)) => max.checked_mul(node_max),
+                _ => None,
+            };
+            (min.saturating_mul(node_min), max)
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Builds strings from a parsed pattern: samples that are likely to match,
+//! near-misses one edit away from a sample, and random strings over the
+//! chars the pattern mentions.
+
+use std::collections::HashMap;
+
+use crate::ast::{self, BackreferenceKind, ClassItem, GroupKind, RegexFlags, RegexNode};
+use crate::dialect;
+use crate::matcher;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+// Chars mixed into every alphabet, covering each character type
+const CHAR_POOL: &[char] = &[
+    'a', 'b', 'c', 'x', 'y', 'z', 'A', 'B', 'C', 'X', 'Y', 'Z', '0', '1', '5', '9', '_', ' ', '-',
+    '.', ',', '!', '$', '\n', '\t', '\r', 'é', '☺', '😀',
+];
+
+// Random chars tried when nothing in the pool fits, e.g. for `[^\s\S]`
+const CHAR_ATTEMPTS: usize = 200;
+
+pub struct Generator {
+    rng: StdRng,
+    max_repeat: usize,
+    max_length: usize,
+    max_sample_length: usize,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Generator {
+    pub fn new() -> Self {
+        Generator {
+            rng: StdRng::from_entropy(),
+            max_repeat: 3,
+            max_length: 10,
+            max_sample_length: 1_000,
+        }
+    }
+
+    /// A generator whose output is fully determined by the seed.
+    pub fn with_seed(seed: u64) -> Self {
+        Generator {
+            rng: StdRng::seed_from_u64(seed),
+            ..Self::new()
+        }
+    }
+
+    /// How many repetitions past its minimum a quantifier may take. This also
+    /// caps unbounded quantifiers such as `*` and `+`.
+    pub fn with_max_repeat(mut self, max_repeat: usize) -> Self {
+        self.max_repeat = max_repeat;
+        self
+    }
+
+    /// The longest string [`random_string`](Self::random_string) returns.
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// The longest sample to build. Repetitions stop once a sample reaches
+    /// it, so patterns like `a{999999}` give a short string that doesn't
+    /// match rather than one too long to check.
+    pub fn with_max_sample_length(mut self, max_sample_length: usize) -> Self {
+        self.max_sample_length = max_sample_length;
+        self
+    }
+
+    /// A string the pattern is likely to match in full. Lookarounds, anchors
+    /// and word boundaries are ignored, so not every sample is a match.
+    pub fn sample(&mut self, nodes: &[RegexNode]) -> String {
+        self.sample_chars(nodes).into_iter().collect()
+    }
+
+    /// A sample with one char removed, inserted, replaced or swapped with
+    /// its neighbour. Most near-misses no longer match.
+    pub fn near_miss(&mut self, nodes: &[RegexNode]) -> String {
+        let sample = self.sample_chars(nodes);
+        let alphabet = alphabet(nodes);
+        self.mutate(sample, &alphabet).into_iter().collect()
+    }
+
+    /// A string of random length made of the chars the pattern mentions and
+    /// a shared pool of others.
+    pub fn random_string(&mut self, nodes: &[RegexNode]) -> String {
+        let alphabet = alphabet(nodes);
+        let len = self.rng.gen_range(0..=self.max_length);
+        (0..len).map(|_| *alphabet.choose(&mut self.rng).unwrap()).collect()
+    }
+
+    fn sample_chars(&mut self, nodes: &[RegexNode]) -> Vec<char> {
+        let mut sampler = Sampler::new(nodes, &mut self.rng, self.max_repeat, self.max_sample_length);
+        let mut out = Vec::new();
+        sampler.sample_sequence(nodes, &RegexFlags::new(), &mut out);
+        out
+    }
+
+    fn mutate(&mut self, mut sample: Vec<char>, alphabet: &[char]) -> Vec<char> {
+        if sample.is_empty() {
+            return vec![*alphabet.choose(&mut self.rng).unwrap()];
+        }
+
+        let i = self.rng.gen_range(0..sample.len());
+        // Newlines and case changes are what the flags are about, so they get
+        // tried more often than other chars
+        let c = match self.rng.gen_range(0..4) {
+            0 => '\n',
+            1 => swap_case(sample[i]),
+            _ => *alphabet.choose(&mut self.rng).unwrap(),
+        };
+        match self.rng.gen_range(0..4) {
+            0 => {
+                sample.remove(i);
+            }
+            1 => sample.insert(i, c),
+            2 => sample[i] = c,
+            _ if i + 1 < sample.len() => sample.swap(i, i + 1),
+            _ => sample.push(c),
+        }
+        sample
+    }
+}
+
+fn swap_case(c: char) -> char {
+    let swapped: Vec<char> = if c.is_lowercase() {
+        c.to_uppercase().collect()
+    } else {
+        c.to_lowercase().collect()
+    };
+    match swapped[..] {
+        [swapped] => swapped,
+        _ => c,
+    }
+}
+
+/// The chars a pattern mentions, along with the shared pool.
+pub(crate) fn alphabet(nodes: &[RegexNode]) -> Vec<char> {
+    let mut chars: Vec<char> = CHAR_POOL.to_vec();
+    ast::walk(nodes, &mut |node| match node {
+        RegexNode::Literal(c) => chars.push(*c),
+        RegexNode::CharacterClass { items, .. } => {
+            for item in items {
+                match item {
+                    ClassItem::Range { start, end } => chars.extend([*start, *end]),
+                    item => chars.extend(item.as_char()),
+                }
+            }
+        }
+        _ => {}
+    });
+    chars.sort_unstable();
+    chars.dedup();
+    chars
+}
+
+/// How many plain alternatives a sequence stands for once nested
+/// alternations are flattened, used to weight the branches of an
+/// alternation. Without it `(?:a|b|c)|d` would produce `d` half the time.
+fn branch_weight(nodes: &[RegexNode]) -> usize {
+    nodes
+        .iter()
+        .map(|node| match node {
+            RegexNode::Alternation(alternatives) => {
+                alternatives.iter().map(|alt| branch_weight(alt)).sum()
+            }
+            RegexNode::Group(_, nodes) | RegexNode::FlagSet(_, _, nodes) => branch_weight(nodes),
+            _ => 1,
+        })
+        .max()
+        .unwrap_or(1)
+}
+
+struct Sampler<'a> {
+    rng: &'a mut StdRng,
+    max_repeat: usize,
+    max_length: usize,
+    group_indices: HashMap<*const RegexNode, usize>,
+    // Text each group captured so far, for backreferences
+    groups: HashMap<usize, Vec<char>>,
+    names: HashMap<&'a str, usize>,
+}
+
+impl<'a> Sampler<'a> {
+    fn new(nodes: &'a [RegexNode], rng: &'a mut StdRng, max_repeat: usize, max_length: usize) -> Self {
+        let mut group_indices = HashMap::new();
+        let mut names = HashMap::new();
+        ast::walk(nodes, &mut |node| {
+            if let RegexNode::Group(GroupKind::Capturing(name), _) = node {
+                let index = group_indices.len() + 1;
+                group_indices.insert(node as *const RegexNode, index);
+                if let Some(name) = name {
+                    names.entry(name.as_str()).or_insert(index);
+                }
+            }
+        });
+
+        Sampler {
+            rng,
+            max_repeat,
+            max_length,
+            group_indices,
+            groups: HashMap::new(),
+            names,
+        }
+    }
+
+    fn sample_sequence(&mut self, nodes: &[RegexNode], flags: &RegexFlags, out: &mut Vec<char>) {
+        for node in nodes {
+            self.sample_node(node, flags, out);
+        }
+    }
+
+    fn sample_node(&mut self, node: &RegexNode, flags: &RegexFlags, out: &mut Vec<char>) {
+        match node {
+            RegexNode::Literal(c) if flags.case_insensitive && self.rng.gen_bool(0.5) => {
+                out.push(swap_case(*c))
+            }
+            RegexNode::Literal(c) => out.push(*c),
+            RegexNode::CharacterClass { negated: false, items } if !items.is_empty() => {
+                let item = items.choose(&mut *self.rng).unwrap();
+                let c = match item {
+                    ClassItem::Range { start, end } => {
+                        let c = self.rng.gen_range(*start as u32..=*end as u32);
+                        char::from_u32(c).unwrap_or(*start)
+                    }
+                    item => match item.as_char() {
+                        Some(c) => c,
+                        None => {
+                            let single = RegexNode::new_class(vec![item.clone()], false);
+                            self.pick_char(&single, flags)
+                        }
+                    },
+                };
+                out.push(c);
+            }
+            RegexNode::Dot
+            | RegexNode::CharacterClass { .. }
+            | RegexNode::CharacterType(_)
+            | RegexNode::UnicodeCategory { .. } => out.push(self.pick_char(node, flags)),
+            RegexNode::Quantified { node, quantifier } => {
+                let (min, max, _) = matcher::bounds(quantifier);
+                let max = max.unwrap_or(usize::MAX).min(min.saturating_add(self.max_repeat));
+                let mut count = self.rng.gen_range(min..=max);
+                // Repeating something that never adds a char changes nothing
+                if dialect::node_width(node).1 == Some(0) {
+                    count = count.min(1);
+                }
+                for _ in 0..count {
+                    if out.len() >= self.max_length {
+                        break;
+                    }
+                    self.sample_node(node, flags, out);
+                }
+            }
+            RegexNode::Group(kind, nodes) => {
+                let start = out.len();
+                self.sample_sequence(nodes, flags, out);
+                if let GroupKind::Capturing(_) = kind {
+                    let index = self.group_indices[&(node as *const RegexNode)];
+                    self.groups.insert(index, out[start..].to_vec());
+                }
+            }
+            RegexNode::Backreference(kind) => {
+                let index = match kind {
+                    BackreferenceKind::NumberBased(n) => Some(*n),
+                    BackreferenceKind::NameBased(name) => self.names.get(name.as_str()).copied(),
+                };
+                if let Some(text) = index.and_then(|index| self.groups.get(&index)) {
+                    out.extend(text);
+                }
+            }
+            RegexNode::Alternation(alternatives) => {
+                let alt = alternatives
+                    .choose_weighted(&mut *self.rng, |alt| branch_weight(alt))
+                    .unwrap();
+                self.sample_sequence(alt, flags, out);
+            }
+            RegexNode::FlagSet(new_flags, _, nodes) => {
+                self.sample_sequence(nodes, &flags.apply(new_flags), out)
+            }
+            RegexNode::Anchor(_) | RegexNode::WordBoundary | RegexNode::Lookaround(..) => {}
+        }
+    }
+
+    /// A char that `node` matches, or any char if none can be found.
+    fn pick_char(&mut self, node: &RegexNode, flags: &RegexFlags) -> char {
+        let candidates: Vec<char> = CHAR_POOL
+            .iter()
+            .copied()
+            .filter(|&c| matcher::char_matches(node, c, flags))
+            .collect();
+        if let Some(&c) = candidates.choose(&mut *self.rng) {
+            return c;
+        }
+
+        // Negated classes and categories may rule out the whole pool
+        for _ in 0..CHAR_ATTEMPTS {
+            let c = self.rng.gen_range(' '..='\u{FFFF}');
+            if matcher::char_matches(node, c, flags) {
+                return c;
+            }
+        }
+        *CHAR_POOL.choose(&mut *self.rng).unwrap()
+    }
+}
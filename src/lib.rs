@@ -0,0 +1,16 @@
+pub mod ast;
+pub mod parser;
+pub mod printer;
+pub mod obfuscator;
+pub mod passes;
+pub mod matcher;
+pub mod nfa;
+pub mod dfa;
+pub mod generate;
+pub mod verify;
+pub mod redos;
+pub mod stats;
+pub mod dialect;
+pub mod translate;
+#[allow(clippy::module_inception)]
+mod tests;
@@ -1,42 +1,217 @@
-mod ast;
-mod parser;
-mod printer;
-mod obfuscator;
-mod tests;
+use std::env;
+use std::process;
 
-use parser::Parser;
-use printer::Printer;
-use obfuscator::Obfuscator;
+use yugen::ast::RegexNode;
+use yugen::dialect::Dialect;
+use yugen::parser::Parser;
+use yugen::printer::{PrintError, Printer};
+use yugen::obfuscator::{Intensity, ObfuscationBudget, Obfuscator};
+use yugen::stats::Stats;
+use yugen::translate;
+use yugen::verify::Verifier;
 
-fn main() {
-    let test_patterns = vec![
-        "hello",           // Simple literals
-        "[abc]",          // Basic character class
-        "[a-z]",          // Character class with range
-        "foo[bar]baz",    // Character class in context
-        "[^abc]",         // Negated character class (will be preserved)
+const USAGE: &str = "usage: yugen [--seed <u64>] [--dialect <name>] [--to <name>] [--intensity light|medium|heavy|paranoid] [--verify] [--max-length <n>] [--max-growth <x>] [pattern...]";
+
+struct Options {
+    seed: Option<u64>,
+    dialect: Dialect,
+    /// Translate into this dialect instead of obfuscating
+    target: Option<Dialect>,
+    intensity: Intensity,
+    verify: bool,
+    max_length: Option<usize>,
+    max_growth: Option<f64>,
+    patterns: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        seed: None,
+        dialect: Dialect::Generic,
+        target: None,
+        intensity: Intensity::Medium,
+        verify: false,
+        max_length: None,
+        max_growth: None,
+        patterns: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a value")?;
+                let seed = value
+                    .parse::<u64>()
+                    .map_err(|_| format!("invalid seed `{}`", value))?;
+                options.seed = Some(seed);
+            }
+            "--dialect" => {
+                let value = args.next().ok_or("--dialect needs a value")?;
+                options.dialect = value.parse()?;
+            }
+            "--to" => {
+                let value = args.next().ok_or("--to needs a value")?;
+                options.target = Some(value.parse()?);
+            }
+            "--intensity" => {
+                let value = args.next().ok_or("--intensity needs a value")?;
+                options.intensity = value.parse()?;
+            }
+            "--verify" => options.verify = true,
+            "--max-length" => {
+                let value = args.next().ok_or("--max-length needs a value")?;
+                let max_length = value
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid length `{}`", value))?;
+                options.max_length = Some(max_length);
+            }
+            "--max-growth" => {
+                let value = args.next().ok_or("--max-growth needs a value")?;
+                let max_growth = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|growth| growth.is_finite() && *growth >= 0.0)
+                    .ok_or_else(|| format!("invalid growth factor `{}`", value))?;
+                options.max_growth = Some(max_growth);
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            // Everything after `--` is a pattern, even if it starts with `-`
+            "--" => options.patterns.extend(args.by_ref()),
+            _ => options.patterns.push(arg),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Prints the stats of a pattern before and after obfuscation side by side.
+fn print_comparison(before: &Stats, after: &Stats) {
+    let rows = [
+        ("nodes", before.node_count, after.node_count),
+        ("max depth", before.max_depth, after.max_depth),
+        ("capture groups", before.capture_groups, after.capture_groups),
+        ("printed length", before.printed_length, after.printed_length),
+        ("alternation branches", before.alternation_branches, after.alternation_branches),
+        ("NFA states (est.)", before.nfa_states, after.nfa_states),
     ];
+    println!("{:<22}{:>8}{:>8}", "", "before", "after");
+    for (name, before, after) in rows {
+        println!("{:<22}{:>8}{:>8}", name, before, after);
+    }
+    println!("{:<22}{:>16.1}x", "growth", before.growth(after));
+}
+
+/// Prints the obfuscated pattern with and without unicode escapes, then its
+/// stats next to those of the input.
+fn print_obfuscated(ast: &[RegexNode], obfuscated_ast: &[RegexNode], dialect: Dialect) -> Result<(), PrintError> {
+    let printer = Printer::new(true).with_dialect(dialect);
+    println!("Obfuscated pattern: {}", printer.try_print(obfuscated_ast)?);
+
+    // Print without Unicode escapes to verify it's equivalent
+    let normal_printer = Printer::new(false).with_dialect(dialect);
+    println!("Same pattern without escapes: {}", normal_printer.try_print(obfuscated_ast)?);
+
+    print_comparison(&Stats::of(ast, &printer)?, &Stats::of(obfuscated_ast, &printer)?);
+    Ok(())
+}
 
-    for pattern in test_patterns {
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
+
+    let test_patterns = if options.patterns.is_empty() {
+        vec![
+            "hello",           // Simple literals
+            "[abc]",          // Basic character class
+            "[a-z]",          // Character class with range
+            "foo[bar]baz",    // Character class in context
+            "[^abc]",         // Negated character class (will be preserved)
+            "a(b|c",          // Unclosed group (reports a parse error)
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect()
+    } else {
+        options.patterns
+    };
+
+    // One obfuscator for the whole run, so a seed fixes every pattern's output
+    let mut builder = Obfuscator::builder()
+        .intensity(options.intensity)
+        .dialect(options.dialect);
+    if let Some(seed) = options.seed {
+        builder = builder.seed(seed);
+    }
+    if options.max_length.is_some() || options.max_growth.is_some() {
+        // Measured as printed below, with unicode escapes
+        builder = builder.budget(ObfuscationBudget {
+            max_length: options.max_length,
+            max_growth: options.max_growth,
+            unicode_escapes: true,
+        });
+    }
+    let mut obfuscator = builder.build();
+
+    let mut verifier = match options.seed {
+        Some(seed) => Verifier::with_seed(seed),
+        None => Verifier::new(),
+    };
+    let mut failed = false;
+
+    for pattern in &test_patterns {
         println!("\nProcessing pattern: {}", pattern);
-        
+
         // Parse the pattern into AST
-        let mut parser = Parser::new(pattern);
-        let ast = parser.parse().unwrap();
-        
+        let mut parser = Parser::new(pattern).with_dialect(options.dialect);
+        let ast = match parser.parse() {
+            Ok(ast) => ast,
+            Err(err) => {
+                eprintln!("{}", err.render(pattern));
+                continue;
+            }
+        };
+
+        if let Some(target) = options.target {
+            match translate::translate(&ast, target) {
+                Ok(translated) => match Printer::new(false).with_dialect(target).try_print(&translated) {
+                    Ok(printed) => println!("Translated to {}: {}", target, printed),
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        failed = true;
+                    }
+                },
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    failed = true;
+                }
+            }
+            continue;
+        }
+
         // Obfuscate the AST
-        let mut obfuscator = Obfuscator::new();
-        let obfuscated_ast = obfuscator.obfuscate(ast);
-        
-        // Convert back to string with Unicode escapes
-        let printer = Printer::new(true);
-        let obfuscated_pattern = printer.print(&obfuscated_ast);
-        
-        println!("Obfuscated pattern: {}", obfuscated_pattern);
-        
-        // Print without Unicode escapes to verify it's equivalent
-        let normal_printer = Printer::new(false);
-        let normal_pattern = normal_printer.print(&obfuscated_ast);
-        println!("Same pattern without escapes: {}", normal_pattern);
+        let obfuscated_ast = obfuscator.obfuscate(ast.clone());
+
+        // Refuse to print a pattern that behaves differently from the input
+        if options.verify {
+            if let Err(mismatch) = verifier.verify(&ast, &obfuscated_ast) {
+                eprintln!("verification failed: {}", mismatch);
+                failed = true;
+                continue;
+            }
+        }
+
+        if let Err(err) = print_obfuscated(&ast, &obfuscated_ast, options.dialect) {
+            eprintln!("error: {}", err);
+            failed = true;
+        }
+    }
+
+    if failed {
+        process::exit(1);
     }
 }
@@ -0,0 +1,656 @@
+//! A backtracking matcher that runs a parsed pattern directly, so obfuscated
+//! output can be checked against the original without an outside engine.
+//!
+//! The pattern is compiled into a small program first, which is run with a
+//! stack of the choices left to try rather than by recursion, so how long
+//! the haystack is has no bearing on the call stack.
+//!
+//! Semantics follow PCRE where engines disagree, with a few simplifications:
+//! `\d` and `\w` are ASCII only, `.` excludes just `\n`, and `\p{..}`
+//! categories are approximated with the character tests in `std`.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{
+    self, AnchorType, BackreferenceKind, CharacterTypeKind, ClassItem, GroupKind, LookaroundKind,
+    Quantifier, QuantifierMode, RegexFlags, RegexNode, UnicodeCategoryKind,
+};
+use crate::nfa::{self, Assertion};
+use crate::parser::Span;
+
+pub struct Matcher<'a> {
+    insts: Vec<Inst<'a>>,
+    start: InstId,
+    group_count: usize,
+    // Loops in the pattern, each with its own counter
+    repeat_count: usize,
+    // Group number for each name, the first group wins if a name repeats
+    names: HashMap<&'a str, usize>,
+    step_limit: usize,
+}
+
+/// A successful match. All spans are char offsets into the haystack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    // Index 0 is the whole match, then one entry per capturing group
+    groups: Vec<Option<Span>>,
+    names: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchError {
+    /// The pattern backtracked more than the matcher's step limit allows.
+    StepLimitExceeded,
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchError::StepLimitExceeded => write!(f, "step limit exceeded while matching"),
+        }
+    }
+}
+
+impl std::error::Error for MatchError {}
+
+type InstId = usize;
+
+enum Inst<'a> {
+    /// Consumes one char the node matches under the flags.
+    Char { node: &'a RegexNode, flags: RegexFlags, next: InstId },
+    Assert { assertion: Assertion, next: InstId },
+    /// Continues at both, trying the first one first.
+    Split(InstId, InstId),
+    /// Notes where capturing group `index` starts.
+    GroupStart { index: usize, next: InstId },
+    /// Sets capturing group `index` to end here.
+    GroupEnd { index: usize, next: InstId },
+    /// Matches what group `index` holds, never matching if it holds nothing.
+    Backreference { index: Option<usize>, flags: RegexFlags, next: InstId },
+    /// Sets a loop's counter to zero before its first repetition.
+    RepeatStart { repeat: usize, next: InstId },
+    /// Picks between another repetition at `body` and leaving at `exit`.
+    Repeat { repeat: usize, min: usize, max: Option<usize>, lazy: bool, body: InstId, exit: InstId },
+    /// Notes where a repetition starts.
+    RepeatBody { repeat: usize, next: InstId },
+    /// Counts a finished repetition. Past `min`, a repetition that matched
+    /// the empty string fails, as it could otherwise go forever.
+    RepeatEnd { repeat: usize, min: usize, next: InstId },
+    /// Runs `body` as far as its first match only.
+    Atomic { body: InstId, next: InstId },
+    Lookaround { kind: LookaroundKind, body: InstId, next: InstId },
+    /// Ends the program, and the bodies of atomic groups and lookarounds.
+    Match,
+}
+
+impl Inst<'_> {
+    fn is_step(&self) -> bool {
+        !matches!(
+            self,
+            Inst::Split(..) | Inst::GroupEnd { .. } | Inst::Repeat { .. } | Inst::RepeatEnd { .. } | Inst::Match
+        )
+    }
+}
+
+/// An entry on the backtrack stack: a choice left to try, or a value to put
+/// back on the way to one.
+enum Frame {
+    Retry { inst: InstId, pos: usize },
+    Group { index: usize, span: Option<Span> },
+    Groups(Vec<Option<Span>>),
+    GroupStart { index: usize, pos: usize },
+    Count { repeat: usize, count: usize },
+    RepeatStart { repeat: usize, pos: usize },
+}
+
+struct State {
+    input: Vec<char>,
+    groups: Vec<Option<Span>>,
+    group_starts: Vec<usize>,
+    counts: Vec<usize>,
+    repeat_starts: Vec<usize>,
+    steps: usize,
+    step_limit: usize,
+}
+
+impl State {
+    fn step(&mut self) -> bool {
+        self.steps += 1;
+        self.steps <= self.step_limit
+    }
+
+    fn aborted(&self) -> bool {
+        self.steps > self.step_limit
+    }
+}
+
+impl Match {
+    pub(crate) fn new(groups: Vec<Option<Span>>, names: HashMap<String, usize>) -> Self {
+        Match { groups, names }
+    }
+
+    pub fn span(&self) -> Span {
+        self.groups[0].expect("the whole match always has a span")
+    }
+
+    /// The span of capturing group `index`, counting from 1, or `None` if the
+    /// group did not take part in the match.
+    pub fn group(&self, index: usize) -> Option<Span> {
+        self.groups.get(index).copied().flatten()
+    }
+
+    pub fn name(&self, name: &str) -> Option<Span> {
+        self.names.get(name).and_then(|&index| self.group(index))
+    }
+
+    /// Spans of every capturing group in order, without the whole match.
+    pub fn groups(&self) -> &[Option<Span>] {
+        &self.groups[1..]
+    }
+
+    /// The text the span covers in `haystack`, which must be the string the
+    /// match came from.
+    pub fn text(haystack: &str, span: Span) -> String {
+        haystack.chars().skip(span.start).take(span.end - span.start).collect()
+    }
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(nodes: &'a [RegexNode]) -> Self {
+        let mut compiler = Compiler::new(nodes);
+        let accept = compiler.push(Inst::Match);
+        let start = compiler.compile_sequence(nodes, &RegexFlags::new(), accept);
+
+        Matcher {
+            insts: compiler.insts,
+            start,
+            group_count: compiler.group_count,
+            repeat_count: compiler.repeat_count,
+            names: compiler.names,
+            step_limit: 1_000_000,
+        }
+    }
+
+    /// Caps how many instructions may run in a single search, so patterns
+    /// with catastrophic backtracking fail instead of hanging.
+    pub fn with_step_limit(mut self, step_limit: usize) -> Self {
+        self.step_limit = step_limit;
+        self
+    }
+
+    pub fn group_count(&self) -> usize {
+        self.group_count
+    }
+
+    pub fn is_match(&self, haystack: &str) -> Result<bool, MatchError> {
+        Ok(self.find(haystack)?.is_some())
+    }
+
+    /// Finds the leftmost match, preferring alternatives and repetitions the
+    /// way a backtracking engine would.
+    pub fn find(&self, haystack: &str) -> Result<Option<Match>, MatchError> {
+        self.find_at(haystack, 0)
+    }
+
+    /// Like [`find`](Self::find), but only considers matches starting at or
+    /// after char offset `start`.
+    pub fn find_at(&self, haystack: &str, start: usize) -> Result<Option<Match>, MatchError> {
+        let mut state = State {
+            input: haystack.chars().collect(),
+            groups: vec![None; self.group_count + 1],
+            group_starts: vec![0; self.group_count + 1],
+            counts: vec![0; self.repeat_count],
+            repeat_starts: vec![0; self.repeat_count],
+            steps: 0,
+            step_limit: self.step_limit,
+        };
+
+        for from in start..=state.input.len() {
+            let end = self.run(&mut state, self.start, from, None);
+            if state.aborted() {
+                return Err(MatchError::StepLimitExceeded);
+            }
+            if let Some(end) = end {
+                state.groups[0] = Some(Span { start: from, end });
+                let names = self
+                    .names
+                    .iter()
+                    .map(|(name, &index)| (name.to_string(), index))
+                    .collect();
+                return Ok(Some(Match {
+                    groups: state.groups,
+                    names,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Runs the program from `inst` at `pos` and returns where its first
+    /// match ends, or `None` once every choice has failed, with everything
+    /// it changed put back. With `end` set, only a match ending there counts.
+    ///
+    /// Atomic groups and lookarounds run their bodies through a call of their
+    /// own, so the call stack grows only with how they nest in the pattern.
+    fn run(&self, state: &mut State, inst: InstId, pos: usize, end: Option<usize>) -> Option<usize> {
+        let mut stack = Vec::new();
+        let (mut inst, mut pos) = (inst, pos);
+        loop {
+            // Steps are counted per node tried, not per instruction
+            if self.insts[inst].is_step() && !state.step() {
+                return None;
+            }
+            let next = match self.insts[inst] {
+                Inst::Char { node, ref flags, next } => state
+                    .input
+                    .get(pos)
+                    .is_some_and(|&c| char_matches(node, c, flags))
+                    .then(|| {
+                        pos += 1;
+                        next
+                    }),
+                Inst::Assert { assertion, next } => {
+                    nfa::assertion_holds(assertion, &state.input, pos).then_some(next)
+                }
+                Inst::Split(first, second) => {
+                    stack.push(Frame::Retry { inst: second, pos });
+                    Some(first)
+                }
+                Inst::GroupStart { index, next } => {
+                    stack.push(Frame::GroupStart { index, pos: state.group_starts[index] });
+                    state.group_starts[index] = pos;
+                    Some(next)
+                }
+                Inst::GroupEnd { index, next } => {
+                    stack.push(Frame::Group { index, span: state.groups[index] });
+                    state.groups[index] = Some(Span { start: state.group_starts[index], end: pos });
+                    Some(next)
+                }
+                Inst::Backreference { index, ref flags, next } => {
+                    // A reference to a group that hasn't matched never matches
+                    index.and_then(|i| state.groups[i]).and_then(|span| {
+                        let len = span.end - span.start;
+                        let input = &state.input;
+                        let equal = pos + len <= input.len()
+                            && (0..len).all(|i| chars_equal(input[span.start + i], input[pos + i], flags));
+                        equal.then(|| {
+                            pos += len;
+                            next
+                        })
+                    })
+                }
+                Inst::RepeatStart { repeat, next } => {
+                    stack.push(Frame::Count { repeat, count: state.counts[repeat] });
+                    state.counts[repeat] = 0;
+                    Some(next)
+                }
+                Inst::Repeat { repeat, min, max, lazy, body, exit } => {
+                    let count = state.counts[repeat];
+                    if count < min {
+                        Some(body)
+                    } else if max.is_some_and(|max| count >= max) {
+                        Some(exit)
+                    } else if lazy {
+                        // Lazy repetitions try to stop first, greedy ones to go on
+                        stack.push(Frame::Retry { inst: body, pos });
+                        Some(exit)
+                    } else {
+                        stack.push(Frame::Retry { inst: exit, pos });
+                        Some(body)
+                    }
+                }
+                Inst::RepeatBody { repeat, next } => {
+                    stack.push(Frame::RepeatStart { repeat, pos: state.repeat_starts[repeat] });
+                    state.repeat_starts[repeat] = pos;
+                    Some(next)
+                }
+                Inst::RepeatEnd { repeat, min, next } => {
+                    let count = state.counts[repeat];
+                    (count < min || pos != state.repeat_starts[repeat]).then(|| {
+                        stack.push(Frame::Count { repeat, count });
+                        state.counts[repeat] = count + 1;
+                        next
+                    })
+                }
+                Inst::Atomic { body, next } => {
+                    let saved_groups = state.groups.clone();
+                    // Groups the body captured on its way to the first match are kept
+                    self.run(state, body, pos, None).map(|body_end| {
+                        stack.push(Frame::Groups(saved_groups));
+                        pos = body_end;
+                        next
+                    })
+                }
+                Inst::Lookaround { ref kind, body, next } => {
+                    let saved_groups = state.groups.clone();
+                    // Lookarounds are atomic: only the first way the body matches counts
+                    let matched = match kind {
+                        LookaroundKind::PositiveLookahead | LookaroundKind::NegativeLookahead => {
+                            self.run(state, body, pos, None).is_some()
+                        }
+                        // The body must end exactly here; any start before it will do
+                        LookaroundKind::PositiveLookbehind | LookaroundKind::NegativeLookbehind => {
+                            (0..=pos).rev().any(|start| {
+                                !state.aborted() && self.run(state, body, start, Some(pos)).is_some()
+                            })
+                        }
+                    };
+                    let positive = matches!(
+                        kind,
+                        LookaroundKind::PositiveLookahead | LookaroundKind::PositiveLookbehind
+                    );
+                    if matched == positive {
+                        stack.push(Frame::Groups(saved_groups));
+                        Some(next)
+                    } else {
+                        // Nothing a failed body captured is kept
+                        state.groups = saved_groups;
+                        None
+                    }
+                }
+                Inst::Match => {
+                    if end.is_none_or(|end| end == pos) {
+                        return Some(pos);
+                    }
+                    None
+                }
+            };
+
+            match next {
+                Some(next) => inst = next,
+                None => loop {
+                    match stack.pop()? {
+                        Frame::Retry { inst: retry, pos: at } => {
+                            (inst, pos) = (retry, at);
+                            break;
+                        }
+                        Frame::Group { index, span } => state.groups[index] = span,
+                        Frame::Groups(groups) => state.groups = groups,
+                        Frame::GroupStart { index, pos } => state.group_starts[index] = pos,
+                        Frame::Count { repeat, count } => state.counts[repeat] = count,
+                        Frame::RepeatStart { repeat, pos } => state.repeat_starts[repeat] = pos,
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Builds the program back to front like the NFA's compiler: each node is
+/// compiled with the instruction that follows it already known.
+struct Compiler<'a> {
+    insts: Vec<Inst<'a>>,
+    group_count: usize,
+    repeat_count: usize,
+    names: HashMap<&'a str, usize>,
+    // Group number for each capturing group node, keyed by its address
+    group_indices: HashMap<*const RegexNode, usize>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(nodes: &'a [RegexNode]) -> Self {
+        let mut group_count = 0;
+        let mut names = HashMap::new();
+        let mut group_indices = HashMap::new();
+        // Groups are numbered by their opening parenthesis, which is the order
+        // `walk` visits them in
+        ast::walk(nodes, &mut |node| {
+            if let RegexNode::Group(GroupKind::Capturing(name), _) = node {
+                group_count += 1;
+                group_indices.insert(node as *const RegexNode, group_count);
+                if let Some(name) = name {
+                    names.entry(name.as_str()).or_insert(group_count);
+                }
+            }
+        });
+
+        Compiler {
+            insts: Vec::new(),
+            group_count,
+            repeat_count: 0,
+            names,
+            group_indices,
+        }
+    }
+
+    fn push(&mut self, inst: Inst<'a>) -> InstId {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
+
+    fn compile_sequence(&mut self, nodes: &'a [RegexNode], flags: &RegexFlags, next: InstId) -> InstId {
+        nodes
+            .iter()
+            .rev()
+            .fold(next, |next, node| self.compile_node(node, flags, next))
+    }
+
+    fn compile_node(&mut self, node: &'a RegexNode, flags: &RegexFlags, next: InstId) -> InstId {
+        match node {
+            RegexNode::Literal(_)
+            | RegexNode::Dot
+            | RegexNode::CharacterClass { .. }
+            | RegexNode::CharacterType(_)
+            | RegexNode::UnicodeCategory { .. } => self.push(Inst::Char {
+                node,
+                flags: flags.clone(),
+                next,
+            }),
+            RegexNode::Anchor(anchor) => {
+                let multiline = flags.multiline;
+                let assertion = match anchor {
+                    AnchorType::Start => Assertion::Start { multiline },
+                    AnchorType::End => Assertion::End { multiline },
+                };
+                self.push(Inst::Assert { assertion, next })
+            }
+            RegexNode::WordBoundary => self.push(Inst::Assert {
+                assertion: Assertion::WordBoundary,
+                next,
+            }),
+            RegexNode::Quantified { node, quantifier } => {
+                let (min, max, mode) = bounds(quantifier);
+                if mode == QuantifierMode::Possessive {
+                    let accept = self.push(Inst::Match);
+                    let body = self.compile_repeat(node, flags, min, max, false, accept);
+                    return self.push(Inst::Atomic { body, next });
+                }
+                self.compile_repeat(node, flags, min, max, mode == QuantifierMode::Lazy, next)
+            }
+            RegexNode::Group(GroupKind::NonCapturing, nodes) => self.compile_sequence(nodes, flags, next),
+            RegexNode::Group(GroupKind::Atomic, nodes) => {
+                let accept = self.push(Inst::Match);
+                let body = self.compile_sequence(nodes, flags, accept);
+                self.push(Inst::Atomic { body, next })
+            }
+            RegexNode::Group(GroupKind::Capturing(_), nodes) => {
+                let index = self.group_indices[&(node as *const RegexNode)];
+                let end = self.push(Inst::GroupEnd { index, next });
+                let body = self.compile_sequence(nodes, flags, end);
+                self.push(Inst::GroupStart { index, next: body })
+            }
+            RegexNode::Backreference(kind) => {
+                let index = match kind {
+                    BackreferenceKind::NumberBased(n) => Some(*n).filter(|&n| n <= self.group_count),
+                    BackreferenceKind::NameBased(name) => self.names.get(name.as_str()).copied(),
+                };
+                self.push(Inst::Backreference {
+                    index,
+                    flags: flags.clone(),
+                    next,
+                })
+            }
+            RegexNode::Alternation(alternatives) => {
+                let starts: Vec<InstId> = alternatives
+                    .iter()
+                    .map(|alt| self.compile_sequence(alt, flags, next))
+                    .collect();
+                let mut starts = starts.into_iter().rev();
+                let last = starts.next().unwrap_or(next);
+                starts.fold(last, |second, first| self.push(Inst::Split(first, second)))
+            }
+            RegexNode::Lookaround(kind, nodes) => {
+                let accept = self.push(Inst::Match);
+                let body = self.compile_sequence(nodes, flags, accept);
+                self.push(Inst::Lookaround {
+                    kind: kind.clone(),
+                    body,
+                    next,
+                })
+            }
+            RegexNode::FlagSet(new_flags, _, nodes) => {
+                self.compile_sequence(nodes, &flags.apply(new_flags), next)
+            }
+        }
+    }
+
+    /// A loop around `node` with a counter of its own, which tells the
+    /// repetitions needed from the ones that may be given back.
+    fn compile_repeat(
+        &mut self,
+        node: &'a RegexNode,
+        flags: &RegexFlags,
+        min: usize,
+        max: Option<usize>,
+        lazy: bool,
+        next: InstId,
+    ) -> InstId {
+        let repeat = self.repeat_count;
+        self.repeat_count += 1;
+        let decide = self.push(Inst::Match);
+        let end = self.push(Inst::RepeatEnd { repeat, min, next: decide });
+        let body = self.compile_node(node, flags, end);
+        let body = self.push(Inst::RepeatBody { repeat, next: body });
+        self.insts[decide] = Inst::Repeat { repeat, min, max, lazy, body, exit: next };
+        self.push(Inst::RepeatStart { repeat, next: decide })
+    }
+}
+
+/// Whether a node that stands for a single char matches `c`. Any other node
+/// never does.
+pub(crate) fn char_matches(node: &RegexNode, c: char, flags: &RegexFlags) -> bool {
+    match node {
+        RegexNode::Literal(literal) => chars_equal(*literal, c, flags),
+        RegexNode::Dot => flags.dot_all || c != '\n',
+        RegexNode::CharacterClass { negated, items } => {
+            items.iter().any(|item| class_item_matches(item, c, flags)) != *negated
+        }
+        RegexNode::CharacterType(kind) => character_type_matches(kind, c, flags),
+        RegexNode::UnicodeCategory { negated, category } => {
+            unicode_category_matches(category, c) != *negated
+        }
+        _ => false,
+    }
+}
+
+/// The minimum and maximum repetitions of a quantifier, and how it repeats.
+pub(crate) fn bounds(quantifier: &Quantifier) -> (usize, Option<usize>, QuantifierMode) {
+    let mode = quantifier.mode();
+    match *quantifier {
+        Quantifier::ZeroOrMore { .. } => (0, None, mode),
+        Quantifier::OneOrMore { .. } => (1, None, mode),
+        Quantifier::ZeroOrOne { .. } => (0, Some(1), mode),
+        Quantifier::Exactly(n) => (n, Some(n), mode),
+        Quantifier::AtLeast { min, .. } => (min, None, mode),
+        Quantifier::Range { min, max, .. } => (min, Some(max), mode),
+    }
+}
+
+pub(crate) fn chars_equal(a: char, b: char, flags: &RegexFlags) -> bool {
+    a == b || (flags.case_insensitive && case_variants(a).any(|c| c == b))
+}
+
+/// The char itself and its simple lower and upper case forms.
+pub(crate) fn case_variants(c: char) -> impl Iterator<Item = char> {
+    std::iter::once(c)
+        .chain(single_char(c.to_lowercase()))
+        .chain(single_char(c.to_uppercase()))
+}
+
+// Case mappings like `ß` → `SS` have no single-char form
+fn single_char(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    let first = chars.next()?;
+    chars.next().is_none().then_some(first)
+}
+
+fn class_item_matches(item: &ClassItem, c: char, flags: &RegexFlags) -> bool {
+    match item {
+        ClassItem::Char(_) | ClassItem::Escaped(_) => {
+            item.as_char().is_some_and(|item| chars_equal(item, c, flags))
+        }
+        ClassItem::Range { start, end } => {
+            let in_range = |c: char| (*start..=*end).contains(&c);
+            if flags.case_insensitive {
+                case_variants(c).any(in_range)
+            } else {
+                in_range(c)
+            }
+        }
+        ClassItem::CharacterType(kind) => character_type_matches(kind, c, flags),
+        ClassItem::UnicodeCategory { negated, category } => {
+            unicode_category_matches(category, c) != *negated
+        }
+    }
+}
+
+fn character_type_matches(kind: &CharacterTypeKind, c: char, flags: &RegexFlags) -> bool {
+    match kind {
+        CharacterTypeKind::Word => is_word_char(c),
+        CharacterTypeKind::NotWord => !is_word_char(c),
+        CharacterTypeKind::Digit => c.is_ascii_digit(),
+        CharacterTypeKind::NotDigit => !c.is_ascii_digit(),
+        CharacterTypeKind::Whitespace => c.is_whitespace(),
+        CharacterTypeKind::NotWhitespace => !c.is_whitespace(),
+        CharacterTypeKind::EscapedChar(esc) => esc.to_char().is_some_and(|esc| chars_equal(esc, c, flags)),
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn unicode_category_matches(category: &UnicodeCategoryKind, c: char) -> bool {
+    match category {
+        UnicodeCategoryKind::Letter => c.is_alphabetic() && !is_mark(c),
+        UnicodeCategoryKind::Number => c.is_numeric(),
+        UnicodeCategoryKind::Punctuation => is_punctuation(c),
+        UnicodeCategoryKind::Mark => is_mark(c),
+        UnicodeCategoryKind::Separator => c.is_whitespace() && !c.is_control(),
+        UnicodeCategoryKind::Other => c.is_control(),
+        // Whatever visible char is left over
+        UnicodeCategoryKind::Symbol => !(c.is_alphabetic()
+            || c.is_numeric()
+            || c.is_whitespace()
+            || c.is_control()
+            || is_punctuation(c)
+            || is_mark(c)),
+    }
+}
+
+fn is_punctuation(c: char) -> bool {
+    if c.is_ascii() {
+        // `$+<=>^`|~` are symbols
+        return c.is_ascii_punctuation() && !"$+<=>^`|~".contains(c);
+    }
+    matches!(
+        c,
+        '¡' | '§' | '«' | '¶' | '·' | '»' | '¿'
+            | '\u{2010}'..='\u{2027}'
+            | '\u{2030}'..='\u{205E}'
+            | '\u{3001}'..='\u{3003}'
+            | '\u{3008}'..='\u{3011}'
+    )
+}
+
+fn is_mark(c: char) -> bool {
+    // The combining diacritical blocks
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE20}'..='\u{FE2F}'
+    )
+}
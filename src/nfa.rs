@@ -0,0 +1,586 @@
+//! Compiles the regular part of a pattern into a Thompson NFA and runs it
+//! with a PikeVM, which takes time linear in the haystack whatever the
+//! pattern looks like.
+//!
+//! Char tests are resolved into sorted, disjoint char ranges at compile time,
+//! with the same semantics as the [`Matcher`](crate::matcher::Matcher).
+//! Backreferences and lookarounds are not regular and fail to compile.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+use crate::ast::{
+    self, AnchorType, CharacterTypeKind, ClassItem, GroupKind, QuantifierMode, RegexFlags,
+    RegexNode, UnicodeCategoryKind,
+};
+use crate::matcher::{self, Match};
+use crate::parser::Span;
+
+pub type StateId = usize;
+
+/// An inclusive range of chars.
+pub type CharRange = (char, char);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    /// Consumes one char that falls in any of the ranges.
+    Chars { ranges: Vec<CharRange>, next: StateId },
+    /// Continues at both states, preferring the first.
+    Split(StateId, StateId),
+    /// Records the current position in a capture slot.
+    Save { slot: usize, next: StateId },
+    Assert { assertion: Assertion, next: StateId },
+    Match,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assertion {
+    Start { multiline: bool },
+    End { multiline: bool },
+    WordBoundary,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// Backreferences need to remember text, which an NFA cannot.
+    Backreference,
+    Lookaround,
+    /// Atomic groups and possessive quantifiers cut off paths the NFA keeps.
+    Atomic,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Backreference => write!(f, "backreferences cannot be compiled to an NFA"),
+            CompileError::Lookaround => write!(f, "lookarounds cannot be compiled to an NFA"),
+            CompileError::Atomic => {
+                write!(f, "atomic groups and possessive quantifiers cannot be compiled to an NFA")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+pub struct Nfa {
+    states: Vec<State>,
+    start: StateId,
+    group_count: usize,
+    names: HashMap<String, usize>,
+}
+
+impl Nfa {
+    pub fn compile(nodes: &[RegexNode]) -> Result<Nfa, CompileError> {
+        let mut compiler = Compiler::new(nodes);
+        let accept = compiler.push(State::Match);
+        let start = compiler.compile_sequence(nodes, &RegexFlags::new(), accept)?;
+        Ok(Nfa {
+            states: compiler.states,
+            start,
+            group_count: compiler.group_count,
+            names: compiler.names,
+        })
+    }
+
+    pub fn states(&self) -> &[State] {
+        &self.states
+    }
+
+    pub fn start(&self) -> StateId {
+        self.start
+    }
+
+    pub fn group_count(&self) -> usize {
+        self.group_count
+    }
+
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.find(haystack).is_some()
+    }
+
+    /// Finds the leftmost match, with the same preferences among
+    /// alternatives and repetitions as a backtracking engine.
+    pub fn find(&self, haystack: &str) -> Option<Match> {
+        self.find_at(haystack, 0)
+    }
+
+    /// Like [`find`](Self::find), but only considers matches starting at or
+    /// after char offset `start`.
+    pub fn find_at(&self, haystack: &str, start: usize) -> Option<Match> {
+        let input: Vec<char> = haystack.chars().collect();
+        let slot_count = (self.group_count + 1) * 2;
+
+        let mut current = Threads::new(self.states.len());
+        let mut next = Threads::new(self.states.len());
+        let mut matched: Option<Vec<Option<usize>>> = None;
+
+        for pos in start..=input.len() {
+            // A new attempt starts at every position until something matches,
+            // with a lower priority than the attempts already running
+            if matched.is_none() {
+                let mut slots = vec![None; slot_count];
+                slots[0] = Some(pos);
+                self.add_thread(&mut current, self.start, slots, &input, pos);
+            }
+            if current.list.is_empty() && matched.is_some() {
+                break;
+            }
+
+            for (id, slots) in current.list.drain(..) {
+                match &self.states[id] {
+                    State::Match => {
+                        let mut slots = slots;
+                        slots[1] = Some(pos);
+                        matched = Some(slots);
+                        // Threads after this one have a lower priority
+                        break;
+                    }
+                    State::Chars { ranges, next: to } => {
+                        if input.get(pos).is_some_and(|&c| in_ranges(ranges, c)) {
+                            self.add_thread(&mut next, *to, slots, &input, pos + 1);
+                        }
+                    }
+                    _ => unreachable!("only char and match states are queued"),
+                }
+            }
+            current.clear();
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        let slots = matched?;
+        let groups = (0..=self.group_count)
+            .map(|i| match (slots[2 * i], slots[2 * i + 1]) {
+                (Some(start), Some(end)) => Some(Span { start, end }),
+                _ => None,
+            })
+            .collect();
+        Some(Match::new(groups, self.names.clone()))
+    }
+
+    /// Follows every empty transition from `id` and queues the states that
+    /// consume input, in priority order.
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        id: StateId,
+        slots: Vec<Option<usize>>,
+        input: &[char],
+        pos: usize,
+    ) {
+        let mut stack = vec![(id, slots)];
+        while let Some((id, mut slots)) = stack.pop() {
+            if !threads.visit(id) {
+                continue;
+            }
+            match &self.states[id] {
+                State::Chars { .. } | State::Match => threads.list.push((id, slots)),
+                State::Split(first, second) => {
+                    stack.push((*second, slots.clone()));
+                    stack.push((*first, slots));
+                }
+                State::Save { slot, next } => {
+                    slots[*slot] = Some(pos);
+                    stack.push((*next, slots));
+                }
+                State::Assert { assertion, next } => {
+                    if assertion_holds(*assertion, input, pos) {
+                        stack.push((*next, slots));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The threads alive at one position, at most one per state.
+struct Threads {
+    list: Vec<(StateId, Vec<Option<usize>>)>,
+    visited: Vec<bool>,
+}
+
+impl Threads {
+    fn new(state_count: usize) -> Self {
+        Threads {
+            list: Vec::new(),
+            visited: vec![false; state_count],
+        }
+    }
+
+    /// Marks a state as reached, returning whether it was new.
+    fn visit(&mut self, id: StateId) -> bool {
+        !std::mem::replace(&mut self.visited[id], true)
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.visited.iter_mut().for_each(|visited| *visited = false);
+    }
+}
+
+pub(crate) fn assertion_holds(assertion: Assertion, input: &[char], pos: usize) -> bool {
+    let before = pos.checked_sub(1).map(|i| input[i]);
+    assertion_holds_between(assertion, before, input.get(pos).copied())
+}
+
+/// Whether an assertion holds between two chars, `None` standing for either
+/// end of the input.
+pub(crate) fn assertion_holds_between(
+    assertion: Assertion,
+    before: Option<char>,
+    after: Option<char>,
+) -> bool {
+    match assertion {
+        Assertion::Start { multiline } => match before {
+            None => true,
+            Some(c) => multiline && c == '\n',
+        },
+        Assertion::End { multiline } => match after {
+            None => true,
+            Some(c) => multiline && c == '\n',
+        },
+        Assertion::WordBoundary => {
+            let is_word = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+            is_word(before) != is_word(after)
+        }
+    }
+}
+
+pub(crate) fn in_ranges(ranges: &[CharRange], c: char) -> bool {
+    ranges
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                std::cmp::Ordering::Less
+            } else if start > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Builds the NFA back to front: each node is compiled with the state that
+/// follows it already known, so no fragments need patching.
+struct Compiler {
+    states: Vec<State>,
+    group_count: usize,
+    names: HashMap<String, usize>,
+    group_indices: HashMap<*const RegexNode, usize>,
+}
+
+impl Compiler {
+    fn new(nodes: &[RegexNode]) -> Self {
+        let mut group_count = 0;
+        let mut names = HashMap::new();
+        let mut group_indices = HashMap::new();
+        ast::walk(nodes, &mut |node| {
+            if let RegexNode::Group(GroupKind::Capturing(name), _) = node {
+                group_count += 1;
+                group_indices.insert(node as *const RegexNode, group_count);
+                if let Some(name) = name {
+                    names.entry(name.clone()).or_insert(group_count);
+                }
+            }
+        });
+
+        Compiler {
+            states: Vec::new(),
+            group_count,
+            names,
+            group_indices,
+        }
+    }
+
+    fn push(&mut self, state: State) -> StateId {
+        self.states.push(state);
+        self.states.len() - 1
+    }
+
+    fn compile_sequence(
+        &mut self,
+        nodes: &[RegexNode],
+        flags: &RegexFlags,
+        next: StateId,
+    ) -> Result<StateId, CompileError> {
+        let mut next = next;
+        for node in nodes.iter().rev() {
+            next = self.compile_node(node, flags, next)?;
+        }
+        Ok(next)
+    }
+
+    fn compile_node(
+        &mut self,
+        node: &RegexNode,
+        flags: &RegexFlags,
+        next: StateId,
+    ) -> Result<StateId, CompileError> {
+        let id = match node {
+            RegexNode::Literal(_)
+            | RegexNode::Dot
+            | RegexNode::CharacterClass { .. }
+            | RegexNode::CharacterType(_)
+            | RegexNode::UnicodeCategory { .. } => {
+                let ranges = char_ranges(node, flags);
+                self.push(State::Chars { ranges, next })
+            }
+            RegexNode::Anchor(anchor) => {
+                let multiline = flags.multiline;
+                let assertion = match anchor {
+                    AnchorType::Start => Assertion::Start { multiline },
+                    AnchorType::End => Assertion::End { multiline },
+                };
+                self.push(State::Assert { assertion, next })
+            }
+            RegexNode::WordBoundary => self.push(State::Assert {
+                assertion: Assertion::WordBoundary,
+                next,
+            }),
+            RegexNode::Quantified { node, quantifier } => {
+                let (min, max, mode) = matcher::bounds(quantifier);
+                if mode == QuantifierMode::Possessive {
+                    return Err(CompileError::Atomic);
+                }
+                self.compile_repeat(node, flags, min, max, mode == QuantifierMode::Lazy, next)?
+            }
+            RegexNode::Group(GroupKind::NonCapturing, nodes) => {
+                self.compile_sequence(nodes, flags, next)?
+            }
+            RegexNode::Group(GroupKind::Capturing(_), nodes) => {
+                let index = self.group_indices[&(node as *const RegexNode)];
+                let close = self.push(State::Save { slot: 2 * index + 1, next });
+                let body = self.compile_sequence(nodes, flags, close)?;
+                self.push(State::Save { slot: 2 * index, next: body })
+            }
+            RegexNode::Alternation(alternatives) => {
+                let mut starts = Vec::with_capacity(alternatives.len());
+                for alt in alternatives {
+                    starts.push(self.compile_sequence(alt, flags, next)?);
+                }
+                let mut id = starts.pop().unwrap_or(next);
+                for start in starts.into_iter().rev() {
+                    id = self.push(State::Split(start, id));
+                }
+                id
+            }
+            RegexNode::FlagSet(new_flags, _, nodes) => {
+                self.compile_sequence(nodes, &flags.apply(new_flags), next)?
+            }
+            RegexNode::Group(GroupKind::Atomic, _) => return Err(CompileError::Atomic),
+            RegexNode::Backreference(_) => return Err(CompileError::Backreference),
+            RegexNode::Lookaround(..) => return Err(CompileError::Lookaround),
+        };
+        Ok(id)
+    }
+
+    /// `min` copies of the node, then either a loop or `max - min` nested
+    /// optional copies: `x{2,4}` becomes `xx(?:x(?:x)?)?`.
+    fn compile_repeat(
+        &mut self,
+        node: &RegexNode,
+        flags: &RegexFlags,
+        min: usize,
+        max: Option<usize>,
+        lazy: bool,
+        next: StateId,
+    ) -> Result<StateId, CompileError> {
+        let split = |body: StateId, skip: StateId| {
+            if lazy {
+                State::Split(skip, body)
+            } else {
+                State::Split(body, skip)
+            }
+        };
+
+        let mut id = match max {
+            None => {
+                let lp = self.push(State::Split(next, next));
+                let body = self.compile_node(node, flags, lp)?;
+                self.states[lp] = split(body, next);
+                lp
+            }
+            Some(max) => {
+                let mut id = next;
+                for _ in min..max {
+                    let body = self.compile_node(node, flags, id)?;
+                    id = self.push(split(body, next));
+                }
+                id
+            }
+        };
+        for _ in 0..min {
+            id = self.compile_node(node, flags, id)?;
+        }
+        Ok(id)
+    }
+}
+
+/// The chars a single-char node matches under `flags`. Common tests are
+/// worked out directly; anything else is found by testing every char.
+pub(crate) fn char_ranges(node: &RegexNode, flags: &RegexFlags) -> Vec<CharRange> {
+    if let Some(ranges) = simple_ranges(node, flags) {
+        return ranges;
+    }
+    cached_scan(format!("{:?}{:?}", node, flags), |c| matcher::char_matches(node, c, flags))
+}
+
+fn simple_ranges(node: &RegexNode, flags: &RegexFlags) -> Option<Vec<CharRange>> {
+    let ranges = match node {
+        RegexNode::Literal(c) if flags.case_insensitive => {
+            normalize(matcher::case_variants(*c).map(|c| (c, c)).collect())
+        }
+        RegexNode::Literal(c) => vec![(*c, *c)],
+        RegexNode::Dot if flags.dot_all => vec![('\0', char::MAX)],
+        RegexNode::Dot => complement(&[('\n', '\n')]),
+        RegexNode::CharacterType(kind) => type_ranges(kind, flags)?,
+        RegexNode::UnicodeCategory { negated, category } => category_ranges(category, *negated),
+        // Case-insensitive ranges are left to a scan, as case mappings don't
+        // keep ranges contiguous
+        RegexNode::CharacterClass { negated, items } if !flags.case_insensitive => {
+            let mut ranges = Vec::new();
+            for item in items {
+                match item {
+                    ClassItem::Range { start, end } => ranges.push((*start, *end)),
+                    ClassItem::CharacterType(kind) => ranges.extend(type_ranges(kind, flags)?),
+                    ClassItem::UnicodeCategory { negated, category } => {
+                        ranges.extend(category_ranges(category, *negated))
+                    }
+                    item => ranges.extend(item.as_char().map(|c| (c, c))),
+                }
+            }
+            let ranges = normalize(ranges);
+            if *negated {
+                complement(&ranges)
+            } else {
+                ranges
+            }
+        }
+        _ => return None,
+    };
+    Some(ranges)
+}
+
+fn type_ranges(kind: &CharacterTypeKind, flags: &RegexFlags) -> Option<Vec<CharRange>> {
+    let ranges = match kind {
+        CharacterTypeKind::Digit => vec![('0', '9')],
+        CharacterTypeKind::NotDigit => complement(&[('0', '9')]),
+        CharacterTypeKind::Word => word_ranges(),
+        CharacterTypeKind::NotWord => complement(&word_ranges()),
+        CharacterTypeKind::Whitespace => WHITESPACE.to_vec(),
+        CharacterTypeKind::NotWhitespace => complement(WHITESPACE),
+        CharacterTypeKind::EscapedChar(esc) => {
+            let c = esc.to_char()?;
+            return simple_ranges(&RegexNode::Literal(c), flags);
+        }
+    };
+    Some(ranges)
+}
+
+fn category_ranges(category: &UnicodeCategoryKind, negated: bool) -> Vec<CharRange> {
+    let category_node = RegexNode::new_unicode_category(category.clone(), false);
+    let ranges = cached_scan(format!("{:?}", category), |c| {
+        matcher::char_matches(&category_node, c, &RegexFlags::new())
+    });
+    if negated {
+        complement(&ranges)
+    } else {
+        ranges
+    }
+}
+
+// What `char::is_whitespace` accepts
+const WHITESPACE: &[CharRange] = &[
+    ('\t', '\r'),
+    (' ', ' '),
+    ('\u{85}', '\u{85}'),
+    ('\u{A0}', '\u{A0}'),
+    ('\u{1680}', '\u{1680}'),
+    ('\u{2000}', '\u{200A}'),
+    ('\u{2028}', '\u{2029}'),
+    ('\u{202F}', '\u{202F}'),
+    ('\u{205F}', '\u{205F}'),
+    ('\u{3000}', '\u{3000}'),
+];
+
+pub(crate) fn word_ranges() -> Vec<CharRange> {
+    vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')]
+}
+
+/// Like [`scan`], but remembers the result under `key` for the rest of the
+/// process, as scanning every char is slow.
+fn cached_scan(key: String, test: impl Fn(char) -> bool) -> Vec<CharRange> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Vec<CharRange>>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some(ranges) = cache.lock().unwrap().get(&key) {
+        return ranges.clone();
+    }
+    let ranges = scan(test);
+    cache.lock().unwrap().insert(key, ranges.clone());
+    ranges
+}
+
+/// Collects every char that passes `test` into ranges.
+fn scan(test: impl Fn(char) -> bool) -> Vec<CharRange> {
+    let mut ranges: Vec<CharRange> = Vec::new();
+    for c in '\0'..=char::MAX {
+        if !test(c) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some((_, end)) if next_char(*end) == Some(c) => *end = c,
+            _ => ranges.push((c, c)),
+        }
+    }
+    ranges
+}
+
+/// The char after `c`, skipping the surrogate gap.
+pub(crate) fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        c => char::from_u32(c as u32 + 1),
+    }
+}
+
+/// The char before `c`, skipping the surrogate gap.
+pub(crate) fn previous_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        c => char::from_u32((c as u32).checked_sub(1)?),
+    }
+}
+
+/// Sorts ranges and merges the ones that overlap or touch.
+pub(crate) fn normalize(mut ranges: Vec<CharRange>) -> Vec<CharRange> {
+    ranges.sort_unstable();
+    let mut merged: Vec<CharRange> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= *last_end || next_char(*last_end) == Some(start) => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Every char not covered by a set of normalized ranges.
+pub(crate) fn complement(ranges: &[CharRange]) -> Vec<CharRange> {
+    let mut result = Vec::new();
+    let mut from = Some('\0');
+    for &(start, end) in ranges {
+        if let (Some(first), Some(last)) = (from, previous_char(start)) {
+            if first <= last {
+                result.push((first, last));
+            }
+        }
+        from = next_char(end);
+    }
+    if let Some(first) = from {
+        result.push((first, char::MAX));
+    }
+    result
+}
@@ -1,77 +1,692 @@
-use crate::ast::{RegexNode, GroupKind};
-use rand::thread_rng;
+use crate::ast::{self, AnchorType, RegexNode, GroupKind, RegexFlags, BackreferenceKind};
+use crate::dfa::{self, Dfa};
+use crate::dialect::Dialect;
+use crate::nfa::Nfa;
+use crate::printer::Printer;
+use crate::redos;
+use crate::passes::{
+    ClassToAlternation, DotRewrite, GroupRewrite, LiteralToClass, LookaroundNoise,
+    QuantifierRewrite,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+
+/// A single obfuscating transform.
+///
+/// The obfuscator walks the tree once per pass and takes care of recursion:
+/// `rewrite` sees every node after its children have been rewritten, and
+/// `insert_before` may add a node in front of any node in a sequence, an
+/// alternation being put in a group of its own first. Passes should call [`PassContext::chance`] before firing, so their configured
+/// weight is respected.
+pub trait Pass {
+    /// Used to refer to the pass when configuring an [`ObfuscatorBuilder`].
+    fn name(&self) -> &str;
+
+    fn rewrite(&mut self, node: RegexNode, ctx: &mut PassContext) -> RegexNode;
+
+    fn insert_before(&mut self, _next: &RegexNode, _ctx: &mut PassContext) -> Option<RegexNode> {
+        None
+    }
+}
+
+/// State shared with passes while they run.
+pub struct PassContext {
+    rng: StdRng,
+    // Weight of the pass that is currently running
+    weight: f64,
+    // Flags in effect at the node being rewritten
+    flags: RegexFlags,
+    // How many lookarounds enclose the node being rewritten
+    lookaround_depth: usize,
+    // Most non-capturing groups a pass may wrap around one node
+    wrap_depth: usize,
+    captures: CaptureMap,
+}
+
+/// Keeps backreferences pointing at the right group while passes rewrite the
+/// tree, even if they add capturing groups of their own.
+///
+/// Before the passes run, every unnamed input group is given a placeholder
+/// name and every numbered backreference is turned into a reference to that
+/// placeholder. Once they are done, groups are counted again in the output
+/// and placeholders are swapped back for numbers.
+#[derive(Default, Clone)]
+struct CaptureMap {
+    // The name each input group can be found by, indexed by input number - 1
+    keys: Vec<String>,
+    // Names given by passes to groups that were unnamed in the input
+    generated_names: HashMap<String, String>,
+    // Every group name in use, so generated names never collide
+    used_names: HashSet<String>,
+}
+
+// Placeholders start with a character no parsed group name can contain
+const PLACEHOLDER_PREFIX: char = '#';
+
+impl PassContext {
+    fn new(rng: StdRng) -> Self {
+        PassContext {
+            rng,
+            weight: 1.0,
+            flags: RegexFlags::new(),
+            lookaround_depth: 0,
+            wrap_depth: 1,
+            captures: CaptureMap::default(),
+        }
+    }
+
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    /// Decides whether the running pass fires at this spot, based on its
+    /// weight.
+    pub fn chance(&mut self) -> bool {
+        self.weight > 0.0 && self.rng.gen_bool(self.weight.min(1.0))
+    }
+
+    pub fn flags(&self) -> &RegexFlags {
+        &self.flags
+    }
+
+    pub fn in_lookaround(&self) -> bool {
+        self.lookaround_depth > 0
+    }
+
+    /// How many non-capturing groups a pass may stack around a single node.
+    pub fn wrap_depth(&self) -> usize {
+        self.wrap_depth
+    }
+
+    /// Whether a capturing group with this name was unnamed in the input and
+    /// may therefore be given a name of our choosing.
+    pub fn is_unnamed_capture(&self, name: &str) -> bool {
+        name.starts_with(PLACEHOLDER_PREFIX)
+    }
+
+    /// Gives the unnamed group known by `placeholder` a fresh random name,
+    /// which backreferences to it may then use.
+    pub fn name_capture(&mut self, placeholder: &str) {
+        if self.is_unnamed_capture(placeholder)
+            && !self.captures.generated_names.contains_key(placeholder)
+        {
+            let name = self.fresh_group_name();
+            self.captures.generated_names.insert(placeholder.to_string(), name);
+        }
+    }
+
+    pub fn fresh_group_name(&mut self) -> String {
+        const LETTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        const ALPHANUMERIC: &[u8] =
+            b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+        loop {
+            let mut name = String::new();
+            name.push(LETTERS[self.rng.gen_range(0..LETTERS.len())] as char);
+            for _ in 0..5 {
+                name.push(ALPHANUMERIC[self.rng.gen_range(0..ALPHANUMERIC.len())] as char);
+            }
+            if self.captures.used_names.insert(name.clone()) {
+                return name;
+            }
+        }
+    }
+}
 
 pub struct Obfuscator {
-    rng: rand::rngs::ThreadRng,
+    passes: Vec<(Box<dyn Pass>, f64)>,
+    rounds: usize,
+    automaton: bool,
+    reject_redos: bool,
+    budget: Option<ObfuscationBudget>,
+    dialect: Dialect,
+    ctx: PassContext,
 }
 
-impl Obfuscator {
+// Largest minimized DFA rebuilt in automaton mode; state elimination can
+// grow the pattern exponentially in the number of states
+const MAX_AUTOMATON_STATES: usize = 16;
+
+// How many times a pass that overshoots the budget is retried at half weight
+const BUDGET_RETRIES: usize = 4;
+
+/// A limit on how long the printed output may get, either in chars or as a
+/// multiple of the printed input. With both set, the tighter one applies.
+///
+/// Passes that would push the output past the limit are retried firing less
+/// often and skipped if they still don't fit. An input that is already over
+/// the limit comes back with as little obfuscation as possible.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ObfuscationBudget {
+    pub max_length: Option<usize>,
+    pub max_growth: Option<f64>,
+    /// Whether the output will be printed with unicode escapes, which
+    /// changes its length
+    pub unicode_escapes: bool,
+}
+
+impl ObfuscationBudget {
+    pub fn max_length(max_length: usize) -> Self {
+        ObfuscationBudget {
+            max_length: Some(max_length),
+            ..Self::default()
+        }
+    }
+
+    pub fn max_growth(max_growth: f64) -> Self {
+        ObfuscationBudget {
+            max_growth: Some(max_growth),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    pub fn with_max_growth(mut self, max_growth: f64) -> Self {
+        self.max_growth = Some(max_growth);
+        self
+    }
+
+    pub fn with_unicode_escapes(mut self, unicode_escapes: bool) -> Self {
+        self.unicode_escapes = unicode_escapes;
+        self
+    }
+
+    /// The longest output allowed for an input printed in `input_length`
+    /// chars.
+    pub fn limit(&self, input_length: usize) -> usize {
+        let growth_limit = self
+            .max_growth
+            .map(|growth| (input_length as f64 * growth.max(0.0)) as usize);
+        match (self.max_length, growth_limit) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => a.or(b).unwrap_or(usize::MAX),
+        }
+    }
+}
+
+/// Named presets trading readability against output size, from `Light`,
+/// which leaves the pattern mostly recognizable, to `Paranoid`. `Medium` is
+/// the default pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intensity {
+    Light,
+    Medium,
+    Heavy,
+    Paranoid,
+}
+
+struct Preset {
+    // Weight of each default pass, by name
+    weights: [(&'static str, f64); 6],
+    rounds: usize,
+    wrap_depth: usize,
+}
+
+impl Intensity {
+    fn preset(self) -> Preset {
+        let (weights, noise, rounds, wrap_depth) = match self {
+            // Class splitting is left out, as it grows patterns the most
+            Intensity::Light => ([0.0, 0.3, 0.5, 0.3, 0.3], 0.0, 1, 1),
+            Intensity::Medium => ([1.0; 5], 0.0, 1, 1),
+            Intensity::Heavy => ([1.0; 5], 0.2, 2, 2),
+            Intensity::Paranoid => ([1.0; 5], 0.4, 3, 3),
+        };
+        let [class, literal, dot, quantifier, group] = weights;
+        Preset {
+            weights: [
+                (ClassToAlternation.name(), class),
+                (LiteralToClass.name(), literal),
+                (DotRewrite.name(), dot),
+                (QuantifierRewrite.name(), quantifier),
+                (GroupRewrite.name(), group),
+                (LookaroundNoise.name(), noise),
+            ],
+            rounds,
+            wrap_depth,
+        }
+    }
+}
+
+impl std::str::FromStr for Intensity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "light" => Ok(Intensity::Light),
+            "medium" => Ok(Intensity::Medium),
+            "heavy" => Ok(Intensity::Heavy),
+            "paranoid" => Ok(Intensity::Paranoid),
+            _ => Err(format!("unknown intensity `{}`", s)),
+        }
+    }
+}
+
+/// Configures which passes an [`Obfuscator`] runs, in which order and how
+/// often each one fires.
+pub struct ObfuscatorBuilder {
+    passes: Vec<(Box<dyn Pass>, f64)>,
+    rounds: usize,
+    wrap_depth: usize,
+    automaton: bool,
+    reject_redos: bool,
+    budget: Option<ObfuscationBudget>,
+    dialect: Dialect,
+    seed: Option<u64>,
+}
+
+impl Default for ObfuscatorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ObfuscatorBuilder {
+    /// Starts from the default pipeline. Lookaround noise is part of it but
+    /// has a weight of zero.
     pub fn new() -> Self {
+        ObfuscatorBuilder {
+            passes: Vec::new(),
+            rounds: 1,
+            wrap_depth: 1,
+            automaton: false,
+            reject_redos: false,
+            budget: None,
+            dialect: Dialect::Generic,
+            seed: None,
+        }
+        // Classes are split before literals turn into classes of their own
+        .pass(ClassToAlternation)
+        .pass(LiteralToClass)
+        .pass(DotRewrite)
+        .pass(QuantifierRewrite)
+        .pass(GroupRewrite)
+        .weighted_pass(LookaroundNoise, 0.0)
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Applies a preset: the weight of each default pass, how many times the
+    /// pipeline runs over its own output and how deeply groups get wrapped.
+    /// Call [`weight`](Self::weight) afterwards to fine-tune it.
+    pub fn intensity(mut self, intensity: Intensity) -> Self {
+        let preset = intensity.preset();
+        for (name, weight) in preset.weights {
+            self = self.weight(name, weight);
+        }
+        self.rounds = preset.rounds;
+        self.wrap_depth = preset.wrap_depth;
+        self
+    }
+
+    /// Runs the whole pipeline this many times, each round rewriting the
+    /// output of the last.
+    pub fn rounds(mut self, rounds: usize) -> Self {
+        self.rounds = rounds.max(1);
+        self
+    }
+
+    /// The most non-capturing groups [`GroupRewrite`] stacks around a node.
+    pub fn wrap_depth(mut self, wrap_depth: usize) -> Self {
+        self.wrap_depth = wrap_depth;
+        self
+    }
+
+    /// Rebuilds the whole pattern from its minimized DFA before the passes
+    /// run, eliminating states in random order, so the output shares no
+    /// structure with the input. The DFA keeps only the strings the pattern
+    /// matches in full, not where a search finds its match, so only patterns
+    /// anchored as `^...$` are rebuilt, keeping their anchors. Patterns with
+    /// captures, backreferences or lookarounds are left to the passes.
+    pub fn automaton(mut self, enabled: bool) -> Self {
+        self.automaton = enabled;
+        self
+    }
+
+    /// Undoes any pass whose output could backtrack worse than the input, as
+    /// judged by [`redos::analyze`]. For example, splitting `[\w\d]+` into
+    /// `(?:[\w]|[\d])+` would make it exponential.
+    pub fn reject_redos(mut self, enabled: bool) -> Self {
+        self.reject_redos = enabled;
+        self
+    }
+
+    /// The dialect the output will be printed in. Passes whose output it
+    /// can't express are undone, like lookaround noise for RE2.
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Keeps the printed output within `budget`.
+    pub fn budget(mut self, budget: ObfuscationBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Removes every pass, so the pipeline can be rebuilt in another order.
+    pub fn clear(mut self) -> Self {
+        self.passes.clear();
+        self
+    }
+
+    /// Appends a pass that fires wherever it can.
+    pub fn pass(self, pass: impl Pass + 'static) -> Self {
+        self.weighted_pass(pass, 1.0)
+    }
+
+    /// Appends a pass that fires with the given probability, from 0 to 1.
+    pub fn weighted_pass(mut self, pass: impl Pass + 'static, weight: f64) -> Self {
+        self.passes.push((Box::new(pass), weight.clamp(0.0, 1.0)));
+        self
+    }
+
+    /// Changes the weight of every pass with this name.
+    pub fn weight(mut self, name: &str, weight: f64) -> Self {
+        for (pass, pass_weight) in &mut self.passes {
+            if pass.name() == name {
+                *pass_weight = weight.clamp(0.0, 1.0);
+            }
+        }
+        self
+    }
+
+    /// Removes every pass with this name.
+    pub fn disable(mut self, name: &str) -> Self {
+        self.passes.retain(|(pass, _)| pass.name() != name);
+        self
+    }
+
+    pub fn build(self) -> Obfuscator {
+        let rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut ctx = PassContext::new(rng);
+        ctx.wrap_depth = self.wrap_depth;
         Obfuscator {
-            rng: thread_rng(),
-        }
-    }
-
-    pub fn obfuscate(&mut self, ast: Vec<RegexNode>) -> Vec<RegexNode> {
-        ast.into_iter()
-            .map(|node| self.obfuscate_node(node))
-            .collect()
-    }
-
-    fn obfuscate_node(&mut self, node: RegexNode) -> RegexNode {
-        match node {
-            RegexNode::Literal(c) => self.obfuscate_literal(c),
-            RegexNode::CharacterClass { negated, chars } => {
-                if negated {
-                    // Keep negated character classes as is for now
-                    RegexNode::CharacterClass { negated, chars }
-                } else {
-                    // Convert character class to alternation of single-char classes
-                    let alternatives: Vec<Vec<RegexNode>> = chars.into_iter()
-                        .map(|c| {
-                            vec![RegexNode::CharacterClass {
-                                negated: false,
-                                chars: vec![c],
-                            }]
-                        })
-                        .collect();
-
-                    // Wrap in a non-capturing group
-                    RegexNode::Group(
-                        GroupKind::NonCapturing,
-                        vec![RegexNode::Alternation(alternatives)]
-                    )
+            passes: self.passes,
+            rounds: self.rounds,
+            automaton: self.automaton,
+            reject_redos: self.reject_redos,
+            budget: self.budget,
+            dialect: self.dialect,
+            ctx,
+        }
+    }
+}
+
+impl Default for Obfuscator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Obfuscator {
+    pub fn new() -> Self {
+        ObfuscatorBuilder::new().build()
+    }
+
+    /// An obfuscator whose output is fully determined by the seed: the same
+    /// input, seed and options always produce the same pattern.
+    pub fn with_seed(seed: u64) -> Self {
+        ObfuscatorBuilder::new().seed(seed).build()
+    }
+
+    pub fn builder() -> ObfuscatorBuilder {
+        ObfuscatorBuilder::new()
+    }
+
+    /// Inserts assertions that never change what matches, such as
+    /// `(?=[\s\S]|$)` between nodes or `(?=a)` before a literal `a`, each
+    /// with the given probability.
+    pub fn with_lookaround_noise(mut self, probability: f64) -> Self {
+        for (pass, weight) in &mut self.passes {
+            if pass.name() == LookaroundNoise.name() {
+                *weight = probability.clamp(0.0, 1.0);
+            }
+        }
+        self
+    }
+
+    pub fn obfuscate(&mut self, mut ast: Vec<RegexNode>) -> Vec<RegexNode> {
+        // An input the target can't print leaves nothing to measure against
+        let limit = self
+            .budget
+            .map(|budget| budget.limit(self.printed_length(&ast).unwrap_or(usize::MAX)));
+
+        if self.automaton {
+            if let Some(rebuilt) = self.rebuild_from_automaton(&ast) {
+                if self.fits(&rebuilt, limit) {
+                    ast = rebuilt;
                 }
             }
-            RegexNode::Quantified { node, quantifier } => RegexNode::Quantified {
-                node: Box::new(self.obfuscate_node(*node)),
-                quantifier,
-            },
-            RegexNode::Group(kind, nodes) => RegexNode::Group(
-                kind,
-                nodes.into_iter()
-                    .map(|node| self.obfuscate_node(node))
-                    .collect(),
-            ),
-            RegexNode::Alternation(alternatives) => RegexNode::Alternation(
-                alternatives
-                    .into_iter()
-                    .map(|alt| {
-                        alt.into_iter()
-                            .map(|node| self.obfuscate_node(node))
-                            .collect()
-                    })
-                    .collect(),
-            ),
-            // For other node types, return as is
-            _ => node,
-        }
-    }
-
-    fn obfuscate_literal(&mut self, c: char) -> RegexNode {
-        RegexNode::CharacterClass {
-            negated: false,
-            chars: vec![c],
-        }
-    }
-} 
\ No newline at end of file
+        }
+
+        self.ctx.captures = CaptureMap::default();
+        self.mark_captures(&mut ast);
+
+        let baseline = self.reject_redos.then(|| redos::analyze(&ast).complexity);
+        let mut passes = std::mem::take(&mut self.passes);
+        // Each round after the first rewrites what the previous ones produced
+        for _ in 0..self.rounds {
+            for (pass, weight) in &mut passes {
+                // A pass that overshoots the budget, or uses something the
+                // target dialect lacks, is run again firing half as often,
+                // and skipped if that still doesn't fit
+                let mut weight = *weight;
+                for _ in 0..=BUDGET_RETRIES {
+                    self.ctx.weight = weight;
+                    self.ctx.flags = RegexFlags::new();
+                    self.ctx.lookaround_depth = 0;
+                    let captures = self.ctx.captures.clone();
+                    let rewritten = run_sequence(pass.as_mut(), ast.clone(), &mut self.ctx);
+
+                    let too_risky =
+                        baseline.is_some_and(|baseline| redos::analyze(&rewritten).complexity > baseline);
+                    if !too_risky && self.fits(&rewritten, limit) {
+                        ast = rewritten;
+                        break;
+                    }
+                    // Names the pass gave to groups are dropped along with its output
+                    self.ctx.captures = captures;
+                    if too_risky {
+                        break;
+                    }
+                    weight /= 2.0;
+                }
+            }
+        }
+        self.passes = passes;
+
+        let rng = &mut self.ctx.rng;
+        restore_captures(&self.ctx.captures, &mut ast, &mut || rng.gen_bool(0.5));
+        ast
+    }
+
+    /// Whether the target dialect can print the pattern, within `limit`
+    /// chars if there is one. The pattern is measured once captures are
+    /// restored, as long as it can get: with every reference that may use a
+    /// name doing so.
+    fn fits(&self, ast: &[RegexNode], limit: Option<usize>) -> bool {
+        // Anything prints in the generic dialect
+        if limit.is_none() && self.dialect == Dialect::Generic {
+            return true;
+        }
+        let mut restored = ast.to_vec();
+        restore_captures(&self.ctx.captures, &mut restored, &mut || true);
+        self.printed_length(&restored)
+            .is_some_and(|length| limit.is_none_or(|limit| length <= limit))
+    }
+
+    fn printed_length(&self, ast: &[RegexNode]) -> Option<usize> {
+        let budget = self.budget.unwrap_or_default();
+        let printer = Printer::new(budget.unicode_escapes).with_dialect(self.dialect);
+        printer.try_print(ast).ok().map(|printed| printed.chars().count())
+    }
+
+    /// See [`ObfuscatorBuilder::automaton`].
+    fn rebuild_from_automaton(&mut self, ast: &[RegexNode]) -> Option<Vec<RegexNode>> {
+        if !matches_whole_input(ast) || ast.iter().any(|node| node.has_capturing_group()) {
+            return None;
+        }
+        let nfa = Nfa::compile(ast).ok()?;
+        let dfa = Dfa::from_nfa(&nfa, dfa::alphabet(&[&nfa])).minimize();
+        if dfa.states().len() > MAX_AUTOMATON_STATES {
+            return None;
+        }
+        // The rebuilt pattern has no alternation at the top to group
+        let mut rebuilt = vec![RegexNode::new_anchor(AnchorType::Start)];
+        rebuilt.extend(dfa.to_regex(&mut self.ctx.rng));
+        rebuilt.push(RegexNode::new_anchor(AnchorType::End));
+        Some(rebuilt)
+    }
+
+    /// Swaps unnamed groups and numbered backreferences for placeholders,
+    /// see [`CaptureMap`].
+    fn mark_captures(&mut self, ast: &mut [RegexNode]) {
+        let captures = &mut self.ctx.captures;
+        ast::walk_mut(ast, &mut |node| {
+            if let RegexNode::Group(GroupKind::Capturing(name), _) = node {
+                let key = match name {
+                    Some(name) => {
+                        captures.used_names.insert(name.clone());
+                        name.clone()
+                    }
+                    None => {
+                        let placeholder =
+                            format!("{}{}", PLACEHOLDER_PREFIX, captures.keys.len() + 1);
+                        *name = Some(placeholder.clone());
+                        placeholder
+                    }
+                };
+                captures.keys.push(key);
+            }
+        });
+
+        let group_count = captures.keys.len();
+        ast::walk_mut(ast, &mut |node| {
+            if let RegexNode::Backreference(kind) = node {
+                // References to groups that don't exist are left alone
+                if let BackreferenceKind::NumberBased(n @ 1..) = kind {
+                    if *n <= group_count {
+                        *kind = BackreferenceKind::NameBased(format!("{}{}", PLACEHOLDER_PREFIX, n));
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Numbers the groups of the rewritten tree and points every placeholder
+/// reference at its group's new number, or at the name a pass gave it if
+/// `use_name` says so.
+fn restore_captures(
+    captures: &CaptureMap,
+    ast: &mut [RegexNode],
+    use_name: &mut impl FnMut() -> bool,
+) {
+    let mut output_index = HashMap::new();
+    let mut count = 0;
+    ast::walk_mut(ast, &mut |node| {
+        if let RegexNode::Group(GroupKind::Capturing(name), _) = node {
+            count += 1;
+            if let Some(key) = name.clone() {
+                output_index.entry(key.clone()).or_insert(count);
+                if key.starts_with(PLACEHOLDER_PREFIX) {
+                    *name = captures.generated_names.get(&key).cloned();
+                }
+            }
+        }
+    });
+
+    ast::walk_mut(ast, &mut |node| {
+        let RegexNode::Backreference(BackreferenceKind::NameBased(name)) = node else {
+            return;
+        };
+        let Some(key) = name
+            .strip_prefix(PLACEHOLDER_PREFIX)
+            .and_then(|n| n.parse::<usize>().ok())
+            .and_then(|n| captures.keys.get(n - 1))
+        else {
+            return;
+        };
+        // A pass may have dropped the group; the reference then can't match
+        let Some(&index) = output_index.get(key) else {
+            return;
+        };
+
+        *node = RegexNode::Backreference(match captures.generated_names.get(key) {
+            Some(generated) if use_name() => BackreferenceKind::NameBased(generated.clone()),
+            _ => BackreferenceKind::NumberBased(index),
+        });
+    });
+}
+
+/// Whether every match spans the whole input: the pattern starts with `^`,
+/// ends with `$` and never turns on multiline mode.
+fn matches_whole_input(ast: &[RegexNode]) -> bool {
+    let mut multiline = false;
+    ast::walk(ast, &mut |node| {
+        if let RegexNode::FlagSet(change, _, _) = node {
+            multiline |= change.set.multiline;
+        }
+    });
+    !multiline
+        && matches!(ast.first(), Some(RegexNode::Anchor(AnchorType::Start)))
+        && matches!(ast.last(), Some(RegexNode::Anchor(AnchorType::End)))
+}
+
+fn run_sequence(pass: &mut dyn Pass, nodes: Vec<RegexNode>, ctx: &mut PassContext) -> Vec<RegexNode> {
+    let mut result = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let mut node = run_node(pass, node, ctx);
+        if let Some(inserted) = pass.insert_before(&node, ctx) {
+            // Next to other nodes, an alternation needs a group of its own
+            if matches!(node, RegexNode::Alternation(_)) {
+                node = RegexNode::new_group(GroupKind::NonCapturing, vec![node]);
+            }
+            result.push(inserted);
+        }
+        result.push(node);
+    }
+    result
+}
+
+fn run_node(pass: &mut dyn Pass, node: RegexNode, ctx: &mut PassContext) -> RegexNode {
+    let node = match node {
+        RegexNode::Quantified { node, quantifier } => RegexNode::Quantified {
+            node: Box::new(run_node(pass, *node, ctx)),
+            quantifier,
+        },
+        RegexNode::Group(kind, nodes) => RegexNode::Group(kind, run_sequence(pass, nodes, ctx)),
+        RegexNode::Alternation(alternatives) => RegexNode::Alternation(
+            alternatives
+                .into_iter()
+                .map(|alt| run_sequence(pass, alt, ctx))
+                .collect(),
+        ),
+        RegexNode::FlagSet(change, scope, nodes) => {
+            let outer_flags = ctx.flags.clone();
+            ctx.flags = ctx.flags.apply(&change);
+            let nodes = run_sequence(pass, nodes, ctx);
+            ctx.flags = outer_flags;
+            RegexNode::FlagSet(change, scope, nodes)
+        }
+        RegexNode::Lookaround(kind, nodes) => {
+            ctx.lookaround_depth += 1;
+            let nodes = run_sequence(pass, nodes, ctx);
+            ctx.lookaround_depth -= 1;
+            RegexNode::Lookaround(kind, nodes)
+        }
+        node => node,
+    };
+    pass.rewrite(node, ctx)
+}
@@ -1,20 +1,51 @@
+use std::fmt;
+
 use crate::ast::{
-    AnchorType, BackreferenceKind, CharacterTypeKind, EscapedChar, GroupKind, LookaroundKind,
-    Quantifier, RegexNode, UnicodeCategoryKind, RegexFlags,
+    self, AnchorType, BackreferenceKind, CharacterTypeKind, ClassItem, EscapedChar, GroupKind,
+    LookaroundKind, Quantifier, QuantifierMode, RegexNode, UnicodeCategoryKind, RegexFlags, FlagChange,
+    FlagScope,
 };
+use crate::dialect::{Dialect, Feature};
 
 pub struct Parser {
     input: Vec<char>,
     position: usize,
     group_count: usize,
     current_flags: RegexFlags,
+    dialect: Dialect,
+    // Where the unscoped flag groups at the start of the pattern end, as
+    // Python accepts them nowhere else
+    leading_flags_end: usize,
+    // Numbered backreferences, for checking them once .NET's group numbers
+    // are known
+    backreference_spans: Vec<Span>,
+}
+
+/// A half-open range of offsets into the pattern.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
-#[derive(Debug)]
-pub enum ParseError {
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Char offsets of the offending input
+    pub span: Span,
+    /// Byte offsets of the same input, for slicing the original `&str`
+    pub byte_span: Span,
+    /// What the parser was looking for, e.g. "`)`"
+    pub expected: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
     UnexpectedEndOfInput,
     UnexpectedCharacter(char),
     UnclosedCharacterClass,
+    InvalidClassRange,
+    InvalidClassEscape,
     InvalidQuantifier,
     InvalidNumber,
     UnclosedGroup,
@@ -25,8 +56,105 @@ pub enum ParseError {
     InvalidHexNumber,
     InvalidUnicodeValue,
     EmptyAlternation,
-    InvalidLookaround,
     InvalidFlag,
+    InvalidOctalNumber,
+    /// A letter escape with no meaning in the dialect, or one not modelled
+    /// here, like `\A` or `\Q`
+    InvalidEscape,
+    /// Valid syntax elsewhere, but not in the dialect being parsed
+    Unsupported(Feature, Dialect),
+}
+
+impl ParseErrorKind {
+    fn expected(&self) -> Option<&'static str> {
+        match self {
+            ParseErrorKind::UnexpectedEndOfInput => Some("more input"),
+            ParseErrorKind::UnexpectedCharacter(_) => Some("end of pattern"),
+            ParseErrorKind::UnclosedCharacterClass => Some("`]`"),
+            ParseErrorKind::InvalidClassRange => Some("a range like `a-z` with start <= end"),
+            ParseErrorKind::InvalidClassEscape => Some("a character escape or `\\d`, `\\w`, `\\s`, `\\p{..}`"),
+            ParseErrorKind::InvalidQuantifier => Some("`}`"),
+            ParseErrorKind::InvalidNumber => Some("a decimal number"),
+            ParseErrorKind::UnclosedGroup => Some("`)`"),
+            ParseErrorKind::InvalidGroupSyntax => Some("one of `:`, `=`, `!`, `<`, `>` or flags"),
+            ParseErrorKind::InvalidBackreference => Some("the number or `<name>` of an earlier group"),
+            ParseErrorKind::InvalidGroupName => Some("a name of letters, digits and `_` closed by `>`"),
+            ParseErrorKind::InvalidUnicodeCategory => Some("one of `{L}`, `{N}`, `{P}`, `{S}`, `{M}`, `{Z}`, `{C}`"),
+            ParseErrorKind::InvalidHexNumber => Some("two hex digits"),
+            ParseErrorKind::InvalidUnicodeValue => Some("`{` with 1 to 6 hex digits up to 10FFFF, and `}`"),
+            ParseErrorKind::EmptyAlternation => Some("a pattern on both sides of `|`"),
+            ParseErrorKind::InvalidFlag => Some("flags the dialect knows, like `i`, `m`, `s`, or `-`"),
+            ParseErrorKind::InvalidOctalNumber => Some("octal digits"),
+            ParseErrorKind::InvalidEscape => Some("a known escape like `\\n` or `\\d`, or `\\` before a symbol"),
+            ParseErrorKind::Unsupported(..) => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnexpectedEndOfInput => write!(f, "unexpected end of pattern"),
+            ParseErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character `{}`", c),
+            ParseErrorKind::UnclosedCharacterClass => write!(f, "unclosed character class"),
+            ParseErrorKind::InvalidClassRange => write!(f, "invalid character class range"),
+            ParseErrorKind::InvalidClassEscape => write!(f, "escape is not allowed in a character class"),
+            ParseErrorKind::InvalidQuantifier => write!(f, "invalid quantifier"),
+            ParseErrorKind::InvalidNumber => write!(f, "invalid number"),
+            ParseErrorKind::UnclosedGroup => write!(f, "unclosed group"),
+            ParseErrorKind::InvalidGroupSyntax => write!(f, "invalid group syntax"),
+            ParseErrorKind::InvalidBackreference => write!(f, "invalid backreference"),
+            ParseErrorKind::InvalidGroupName => write!(f, "invalid group name"),
+            ParseErrorKind::InvalidUnicodeCategory => write!(f, "invalid unicode category"),
+            ParseErrorKind::InvalidHexNumber => write!(f, "invalid hex escape"),
+            ParseErrorKind::InvalidUnicodeValue => write!(f, "invalid unicode escape"),
+            ParseErrorKind::EmptyAlternation => write!(f, "empty alternative"),
+            ParseErrorKind::InvalidFlag => write!(f, "invalid flag"),
+            ParseErrorKind::InvalidOctalNumber => write!(f, "invalid octal escape"),
+            ParseErrorKind::InvalidEscape => write!(f, "unknown escape"),
+            ParseErrorKind::Unsupported(feature, dialect) => {
+                write!(f, "{} are not supported in {}", feature, dialect)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.kind, self.span.start)?;
+        if let Some(expected) = self.expected {
+            write!(f, ", expected {}", expected)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ParseError {
+    /// Renders the error against the pattern it came from, with a caret line
+    /// under the offending span:
+    ///
+    /// ```text
+    /// error: unclosed group
+    ///   |
+    ///   | a(bc
+    ///   |  ^ expected `)`
+    /// ```
+    pub fn render(&self, pattern: &str) -> String {
+        let pattern: String = pattern
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        let width = self.span.end.saturating_sub(self.span.start).max(1);
+
+        let mut caret = format!("{}{}", " ".repeat(self.span.start), "^".repeat(width));
+        if let Some(expected) = self.expected {
+            caret.push_str(&format!(" expected {}", expected));
+        }
+
+        format!("error: {}\n  |\n  | {}\n  | {}", self.kind, pattern, caret)
+    }
 }
 
 impl Parser {
@@ -36,39 +164,87 @@ impl Parser {
             position: 0,
             group_count: 0,
             current_flags: RegexFlags::new(),
+            dialect: Dialect::Generic,
+            leading_flags_end: 0,
+            backreference_spans: Vec::new(),
         }
     }
 
+    /// Reads the pattern with the escapes, group syntax and flags of the
+    /// given engine, rejecting what it doesn't support.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
     pub fn parse(&mut self) -> Result<Vec<RegexNode>, ParseError> {
-        self.parse_alternation()
+        let mut nodes = self.parse_alternation()?;
+        // parse_alternation stops at ')', which is only valid inside a group
+        if !self.is_eof() {
+            return Err(self.error(ParseErrorKind::UnexpectedCharacter(self.current())));
+        }
+        if self.dialect == Dialect::DotNet {
+            self.renumber_backreferences(&mut nodes)?;
+        }
+        Ok(nodes)
     }
 
-    fn parse_alternation(&mut self) -> Result<Vec<RegexNode>, ParseError> {
-        let mut alternatives = vec![Vec::new()];
-        
-        while !self.is_eof() {
-            if self.current() == '|' {
-                self.advance();
-                alternatives.push(Vec::new());
-                continue;
+    /// Turns the dialect's group numbers in backreferences into the order
+    /// groups open in, which is what the tree uses, checking each refers
+    /// to a group opened before it.
+    fn renumber_backreferences(&self, nodes: &mut [RegexNode]) -> Result<(), ParseError> {
+        let order = self.dialect.group_order(nodes);
+        let mut spans = self.backreference_spans.iter();
+        let mut opened = 0;
+        let mut result = Ok(());
+        ast::walk_mut(nodes, &mut |node| match node {
+            RegexNode::Group(GroupKind::Capturing(_), _) => opened += 1,
+            RegexNode::Backreference(BackreferenceKind::NumberBased(num)) => {
+                let span = spans.next();
+                match (order.get(*num - 1), span) {
+                    (Some(&position), _) if position <= opened => *num = position,
+                    (_, Some(span)) if result.is_ok() => {
+                        result = Err(self.error_span(
+                            ParseErrorKind::InvalidBackreference,
+                            span.start,
+                            span.end,
+                        ));
+                    }
+                    _ => {}
+                }
             }
+            _ => {}
+        });
+        result
+    }
+
+    fn parse_alternation(&mut self) -> Result<Vec<RegexNode>, ParseError> {
+        // Unscoped flags carry on into later alternatives, and end with the group
+        let entry_flags = self.current_flags.clone();
+        let mut alternatives = Vec::new();
+        let mut last_bar = 0;
+
+        loop {
+            let carried = matching_flag_change(&entry_flags, &self.current_flags);
+            let nodes = self.parse_sequence()?;
+            alternatives.push(carry_flags(carried, nodes));
 
-            // Stop parsing alternation when we hit a closing parenthesis
-            if self.current() == ')' {
+            if self.is_eof() || self.current() != '|' {
                 break;
             }
-
-            let node = self.parse_node()?;
-            if let Some(current_alt) = alternatives.last_mut() {
-                current_alt.push(node);
+            if alternatives.last().is_some_and(|alt| alt.is_empty()) {
+                return Err(self.error(ParseErrorKind::EmptyAlternation));
             }
+            last_bar = self.position;
+            self.advance();
         }
+        self.current_flags = entry_flags;
 
         // If we have multiple alternatives, wrap them in an Alternation node
         if alternatives.len() > 1 {
-            // Check for empty alternatives
+            // Only a trailing empty alternative can be left at this point
             if alternatives.iter().any(|alt| alt.is_empty()) {
-                return Err(ParseError::EmptyAlternation);
+                return Err(self.error_at(ParseErrorKind::EmptyAlternation, last_bar));
             }
             Ok(vec![RegexNode::new_alternation(alternatives)])
         } else {
@@ -77,9 +253,21 @@ impl Parser {
         }
     }
 
+    /// Parses nodes up to the next `|` or `)` at this depth.
+    fn parse_sequence(&mut self) -> Result<Vec<RegexNode>, ParseError> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_insignificant();
+            if self.is_eof() || self.current() == '|' || self.current() == ')' {
+                return Ok(nodes);
+            }
+            nodes.push(self.parse_node()?);
+        }
+    }
+
     fn parse_node(&mut self) -> Result<RegexNode, ParseError> {
         if self.is_eof() {
-            return Err(ParseError::UnexpectedEndOfInput);
+            return Err(self.error(ParseErrorKind::UnexpectedEndOfInput));
         }
 
         let node = match self.current() {
@@ -107,9 +295,19 @@ impl Parser {
             }
         };
 
+        self.skip_insignificant();
         if !self.is_eof() {
             if let Some(quantifier) = self.try_parse_quantifier()? {
-                return Ok(node.with_quantifier(quantifier));
+                let mode = self.parse_quantifier_mode()?;
+                let possessive_exact =
+                    matches!(quantifier, Quantifier::Exactly(_)) && mode == QuantifierMode::Possessive;
+                let node = node.with_quantifier(quantifier.with_mode(mode));
+                // `{n}` has no mode to carry it, but still mustn't be
+                // backtracked into
+                if possessive_exact {
+                    return Ok(RegexNode::new_group(GroupKind::Atomic, vec![node]));
+                }
+                return Ok(node);
             }
         }
 
@@ -124,18 +322,15 @@ impl Parser {
         let quantifier = match self.current() {
             '*' => {
                 self.advance();
-                let lazy = self.check_lazy();
-                Some(Quantifier::ZeroOrMore { lazy })
+                Some(Quantifier::ZeroOrMore { mode: QuantifierMode::Greedy })
             }
             '+' => {
                 self.advance();
-                let lazy = self.check_lazy();
-                Some(Quantifier::OneOrMore { lazy })
+                Some(Quantifier::OneOrMore { mode: QuantifierMode::Greedy })
             }
             '?' => {
                 self.advance();
-                let lazy = self.check_lazy();
-                Some(Quantifier::ZeroOrOne { lazy })
+                Some(Quantifier::ZeroOrOne { mode: QuantifierMode::Greedy })
             }
             '{' => {
                 self.advance();
@@ -147,16 +342,28 @@ impl Parser {
         Ok(quantifier)
     }
 
-    fn check_lazy(&mut self) -> bool {
-        if !self.is_eof() && self.current() == '?' {
-            self.advance();
-            true
+    /// Reads the `?` of a lazy quantifier or the `+` of a possessive one.
+    /// Under `(?U)`, a quantifier without `?` is the lazy one.
+    fn parse_quantifier_mode(&mut self) -> Result<QuantifierMode, ParseError> {
+        let (greedy, lazy) = if self.current_flags.swap_greed {
+            (QuantifierMode::Lazy, QuantifierMode::Greedy)
         } else {
-            false
+            (QuantifierMode::Greedy, QuantifierMode::Lazy)
+        };
+        let (mode, feature) = match self.peek() {
+            Some('?') => (lazy, Feature::LazyQuantifier),
+            Some('+') => (QuantifierMode::Possessive, Feature::PossessiveQuantifier),
+            _ => return Ok(greedy),
+        };
+        self.advance();
+        if !self.dialect.supports(feature) {
+            return Err(self.unsupported(feature, self.position - 2));
         }
+        Ok(mode)
     }
 
     fn parse_curly_quantifier(&mut self) -> Result<Quantifier, ParseError> {
+        let quantifier_start = self.position - 1; // the '{' was already consumed
         let mut num_str = String::new();
         
         while !self.is_eof() && self.current().is_ascii_digit() {
@@ -165,10 +372,10 @@ impl Parser {
         }
         
         let n = num_str.parse::<usize>()
-            .map_err(|_| ParseError::InvalidNumber)?;
+            .map_err(|_| self.error(ParseErrorKind::InvalidNumber))?;
 
         if self.is_eof() {
-            return Err(ParseError::UnexpectedEndOfInput);
+            return Err(self.error(ParseErrorKind::UnexpectedEndOfInput));
         }
 
         match self.current() {
@@ -179,12 +386,12 @@ impl Parser {
             ',' => {
                 self.advance();
                 if self.is_eof() {
-                    return Err(ParseError::UnexpectedEndOfInput);
+                    return Err(self.error(ParseErrorKind::UnexpectedEndOfInput));
                 }
 
                 if self.current() == '}' {
                     self.advance();
-                    Ok(Quantifier::AtLeast(n))
+                    Ok(Quantifier::AtLeast { min: n, mode: QuantifierMode::Greedy })
                 } else {
                     let mut max_str = String::new();
                     while !self.is_eof() && self.current().is_ascii_digit() {
@@ -193,65 +400,134 @@ impl Parser {
                     }
 
                     if self.is_eof() || self.current() != '}' {
-                        return Err(ParseError::InvalidQuantifier);
+                        return Err(self.error(ParseErrorKind::InvalidQuantifier));
                     }
                     self.advance();
 
                     let max = max_str.parse::<usize>()
-                        .map_err(|_| ParseError::InvalidNumber)?;
-                    
-                    Ok(Quantifier::Range { min: n, max })
+                        .map_err(|_| self.error(ParseErrorKind::InvalidNumber))?;
+                    if max < n {
+                        return Err(self.error_span(
+                            ParseErrorKind::InvalidQuantifier,
+                            quantifier_start,
+                            self.position,
+                        ));
+                    }
+
+                    Ok(Quantifier::Range { min: n, max, mode: QuantifierMode::Greedy })
                 }
             }
-            _ => Err(ParseError::InvalidQuantifier),
+            _ => Err(self.error(ParseErrorKind::InvalidQuantifier)),
         }
     }
 
     fn parse_character_class(&mut self) -> Result<RegexNode, ParseError> {
+        let class_start = self.position;
         self.advance(); // consume '['
-        let negated = if self.current() == '^' {
+        let negated = if self.check_char('^') {
             self.advance();
             true
         } else {
             false
         };
 
-        let mut chars = Vec::new();
+        let mut items = Vec::new();
+        // POSIX has no escapes in brackets, but a leading `]` is literal
+        if self.dialect == Dialect::PosixEre && self.check_char(']') {
+            self.advance();
+            items.push(ClassItem::Char(']'));
+        }
         while !self.is_eof() && self.current() != ']' {
-            if self.current() == '\\' {
-                self.advance();
-                if self.is_eof() {
-                    return Err(ParseError::UnexpectedEndOfInput);
+            let item_start = self.position;
+            let item = self.parse_class_item()?;
+
+            // A '-' between two single characters forms a range; a leading or
+            // trailing '-' is just a literal.
+            let is_range = self.check_char('-')
+                && self.position + 1 < self.input.len()
+                && self.input[self.position + 1] != ']';
+
+            if let (true, Some(start)) = (is_range, item.as_char()) {
+                self.advance(); // consume '-'
+                let end = self.parse_class_item()?.as_char();
+                match end {
+                    Some(end) if start <= end => items.push(ClassItem::new_range(start, end)),
+                    _ => {
+                        return Err(self.error_span(
+                            ParseErrorKind::InvalidClassRange,
+                            item_start,
+                            self.position,
+                        ))
+                    }
                 }
-                chars.push(self.current());
-                self.advance();
             } else {
-                chars.push(self.current());
-                self.advance();
+                items.push(item);
             }
         }
 
         if self.is_eof() {
-            return Err(ParseError::UnclosedCharacterClass);
+            return Err(self.error_at(ParseErrorKind::UnclosedCharacterClass, class_start));
         }
 
         self.advance(); // consume ']'
-        Ok(RegexNode::new_char_class(chars, negated))
+        Ok(RegexNode::new_class(items, negated))
+    }
+
+    fn parse_class_item(&mut self) -> Result<ClassItem, ParseError> {
+        let escape_start = self.position;
+        if self.current() != '\\' || self.dialect == Dialect::PosixEre {
+            let c = self.current();
+            self.advance();
+            return Ok(ClassItem::Char(c));
+        }
+
+        self.advance(); // consume '\\'
+        if self.is_eof() {
+            return Err(self.error(ParseErrorKind::UnexpectedEndOfInput));
+        }
+
+        match self.parse_escape()? {
+            RegexNode::CharacterType(CharacterTypeKind::EscapedChar(esc)) => {
+                Ok(ClassItem::Escaped(esc))
+            }
+            RegexNode::CharacterType(kind) => Ok(ClassItem::CharacterType(kind)),
+            RegexNode::UnicodeCategory { negated, category } => {
+                Ok(ClassItem::UnicodeCategory { negated, category })
+            }
+            RegexNode::Literal(c) => Ok(ClassItem::Char(c)),
+            // Word boundaries and backreferences have no meaning inside a class
+            _ => Err(self.error_span(
+                ParseErrorKind::InvalidClassEscape,
+                escape_start,
+                self.position,
+            )),
+        }
     }
 
     fn parse_group(&mut self) -> Result<RegexNode, ParseError> {
+        let group_start = self.position;
         self.advance(); // consume '('
         
         if self.check_char('?') {
             self.advance();
+            if self.is_eof() {
+                return Err(self.error_at(ParseErrorKind::UnclosedGroup, group_start));
+            }
             match self.current() {
-                ':' => {
+                '>' => {
                     self.advance();
-                    let nodes = self.parse_alternation()?;
-                    if self.is_eof() || self.current() != ')' {
-                        return Err(ParseError::UnclosedGroup);
+                    if !self.dialect.supports(Feature::AtomicGroup) {
+                        return Err(self.unsupported(Feature::AtomicGroup, group_start));
                     }
+                    let nodes = self.parse_group_body(group_start)?;
+                    Ok(RegexNode::new_group(GroupKind::Atomic, nodes))
+                }
+                ':' => {
                     self.advance();
+                    if !self.dialect.supports(Feature::NonCapturingGroup) {
+                        return Err(self.unsupported(Feature::NonCapturingGroup, group_start));
+                    }
+                    let nodes = self.parse_group_body(group_start)?;
                     Ok(RegexNode::new_group(GroupKind::NonCapturing, nodes))
                 }
                 '<' => {
@@ -260,11 +536,10 @@ impl Parser {
                         // Lookbehind
                         let negative = self.current() == '!';
                         self.advance();
-                        let nodes = self.parse_alternation()?;
-                        if self.is_eof() || self.current() != ')' {
-                            return Err(ParseError::UnclosedGroup);
+                        let nodes = self.parse_group_body(group_start)?;
+                        if let Err(feature) = self.dialect.check_lookbehind(&nodes) {
+                            return Err(self.unsupported(feature, group_start));
                         }
-                        self.advance();
                         Ok(RegexNode::new_lookaround(
                             if negative {
                                 LookaroundKind::NegativeLookbehind
@@ -273,26 +548,43 @@ impl Parser {
                             },
                             nodes,
                         ))
+                    } else if self.dialect == Dialect::Python {
+                        Err(self.error(ParseErrorKind::InvalidGroupSyntax))
                     } else {
-                        // Named capturing group
-                        let name = self.parse_group_name()?;
-                        let nodes = self.parse_alternation()?;
-                        if self.is_eof() || self.current() != ')' {
-                            return Err(ParseError::UnclosedGroup);
+                        self.parse_named_group(group_start, '>')
+                    }
+                }
+                // (?'name'...)
+                '\'' if matches!(self.dialect, Dialect::Pcre | Dialect::DotNet) => {
+                    self.advance();
+                    self.parse_named_group(group_start, '\'')
+                }
+                'P' if matches!(self.dialect, Dialect::Pcre | Dialect::Python | Dialect::Re2) => {
+                    self.advance();
+                    match self.peek() {
+                        // (?P<name>...)
+                        Some('<') => {
+                            self.advance();
+                            self.parse_named_group(group_start, '>')
                         }
-                        self.advance();
-                        Ok(RegexNode::new_group(GroupKind::Capturing(Some(name)), nodes))
+                        // (?P=name), a backreference
+                        Some('=') if self.dialect != Dialect::Re2 => {
+                            self.advance();
+                            let name = self.parse_group_name(')')?;
+                            Ok(RegexNode::new_backreference(BackreferenceKind::NameBased(name)))
+                        }
+                        Some('=') => Err(self.unsupported(Feature::Backreference, group_start)),
+                        _ => Err(self.error(ParseErrorKind::InvalidGroupSyntax)),
                     }
                 }
                 '=' | '!' => {
                     // Lookahead
-                    let negative = self.current() == '!';
-                    self.advance();
-                    let nodes = self.parse_alternation()?;
-                    if self.is_eof() || self.current() != ')' {
-                        return Err(ParseError::UnclosedGroup);
+                    if !self.dialect.supports(Feature::Lookahead) {
+                        return Err(self.unsupported(Feature::Lookahead, group_start));
                     }
+                    let negative = self.current() == '!';
                     self.advance();
+                    let nodes = self.parse_group_body(group_start)?;
                     Ok(RegexNode::new_lookaround(
                         if negative {
                             LookaroundKind::NegativeLookahead
@@ -302,20 +594,15 @@ impl Parser {
                         nodes,
                     ))
                 }
-                'i' | 'm' | 's' => {
+                'i' | 'm' | 's' | 'x' | 'U' | 'u' | 'n' | '-' => {
                     // Flag setting
-                    let mut new_flags = RegexFlags::new();
-                    while !self.is_eof() && self.current() != ')' && self.current() != ':' {
-                        if let Some(flag) = RegexFlags::from_char(self.current()) {
-                            new_flags = new_flags.merge(&flag);
-                            self.advance();
-                        } else {
-                            return Err(ParseError::InvalidFlag);
-                        }
+                    if !self.dialect.supports(Feature::InlineFlags) {
+                        return Err(self.unsupported(Feature::InlineFlags, group_start));
                     }
+                    let change = self.parse_flag_change()?;
 
                     let old_flags = self.current_flags.clone();
-                    self.current_flags = self.current_flags.merge(&new_flags);
+                    self.current_flags = self.current_flags.apply(&change);
 
                     let has_colon = self.check_char(':');
                     if has_colon {
@@ -324,76 +611,191 @@ impl Parser {
 
                     let nodes = if has_colon {
                         // For scoped flags, parse until closing parenthesis
-                        let nodes = self.parse_alternation()?;
-                        if self.is_eof() || self.current() != ')' {
-                            return Err(ParseError::UnclosedGroup);
-                        }
-                        self.advance();
+                        let nodes = self.parse_group_body(group_start)?;
                         self.current_flags = old_flags; // Restore old flags
                         nodes
                     } else {
+                        // Python takes unscoped flags only at the start of
+                        // the pattern, where they apply to all of it
+                        let leading = self.dialect == Dialect::Python
+                            && group_start == self.leading_flags_end
+                            && change.clear.is_empty();
+                        if !leading && !self.dialect.supports(Feature::UnscopedFlags) {
+                            return Err(self.unsupported(Feature::UnscopedFlags, group_start));
+                        }
+
                         // For unscoped flags, parse until closing parenthesis and then continue
                         if self.is_eof() || self.current() != ')' {
-                            return Err(ParseError::UnclosedGroup);
+                            return Err(self.error_at(ParseErrorKind::UnclosedGroup, group_start));
                         }
                         self.advance();
-                        // Parse the rest of the pattern under these flags
-                        self.parse_alternation()?
+                        if leading {
+                            self.leading_flags_end = self.position;
+                        }
+                        // The flags cover the rest of this alternative, and
+                        // stay set for the later ones until the group ends
+                        self.parse_sequence()?
                     };
 
-                    Ok(RegexNode::new_flag_set(new_flags, nodes))
+                    if has_colon {
+                        Ok(RegexNode::new_scoped_flag_set(change, nodes))
+                    } else {
+                        Ok(RegexNode::new_flag_set(change, nodes))
+                    }
                 }
-                _ => Err(ParseError::InvalidGroupSyntax),
+                _ => Err(self.error(ParseErrorKind::InvalidGroupSyntax)),
             }
+        } else if self.current_flags.no_auto_capture {
+            let nodes = self.parse_group_body(group_start)?;
+            Ok(RegexNode::new_group(GroupKind::NonCapturing, nodes))
         } else {
             self.group_count += 1;
-            let nodes = self.parse_alternation()?;
-            if self.is_eof() || self.current() != ')' {
-                return Err(ParseError::UnclosedGroup);
+            let nodes = self.parse_group_body(group_start)?;
+            Ok(RegexNode::new_group(GroupKind::Capturing(None), nodes))
+        }
+    }
+
+    /// Parses flag letters up to the `:` or `)` after them, those after a
+    /// `-` being turned off.
+    fn parse_flag_change(&mut self) -> Result<FlagChange, ParseError> {
+        let mut change = FlagChange::default();
+        let mut clearing = false;
+        while let Some(c) = self.peek().filter(|&c| c != ')' && c != ':') {
+            let flag = match c {
+                '-' if !clearing => None,
+                c if self.dialect.inline_flags().contains(c) => RegexFlags::from_char(c),
+                _ => return Err(self.error(ParseErrorKind::InvalidFlag)),
+            };
+            match flag {
+                Some(flag) if clearing => change.clear = change.clear.merge(&flag),
+                Some(flag) => change.set = change.set.merge(&flag),
+                None => clearing = true,
             }
             self.advance();
-            Ok(RegexNode::new_group(GroupKind::Capturing(None), nodes))
         }
+        Ok(change)
     }
 
-    fn parse_group_name(&mut self) -> Result<String, ParseError> {
+    /// Skips whitespace and `#` comments where `(?x)` makes them mean nothing.
+    fn skip_insignificant(&mut self) {
+        if !self.current_flags.extended {
+            return;
+        }
+        while let Some(c) = self.peek() {
+            match c {
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.advance();
+                    }
+                }
+                c if c.is_whitespace() => self.advance(),
+                _ => break,
+            }
+        }
+    }
+
+    /// Parses a named capturing group from its name on, the name ending at
+    /// `close`. The tree numbers it as it opens, like any other group.
+    fn parse_named_group(&mut self, group_start: usize, close: char) -> Result<RegexNode, ParseError> {
+        if !self.dialect.supports(Feature::NamedGroup) {
+            return Err(self.unsupported(Feature::NamedGroup, group_start));
+        }
+        let name = self.parse_group_name(close)?;
+        self.group_count += 1;
+        let nodes = self.parse_group_body(group_start)?;
+        Ok(RegexNode::new_group(GroupKind::Capturing(Some(name)), nodes))
+    }
+
+    /// Parses the contents of a group and its closing `)`.
+    fn parse_group_body(&mut self, group_start: usize) -> Result<Vec<RegexNode>, ParseError> {
+        let nodes = self.parse_alternation()?;
+        if self.is_eof() || self.current() != ')' {
+            return Err(self.error_at(ParseErrorKind::UnclosedGroup, group_start));
+        }
+        self.advance();
+        Ok(nodes)
+    }
+
+    fn parse_group_name(&mut self, close: char) -> Result<String, ParseError> {
         let mut name = String::new();
-        while !self.is_eof() && self.current() != '>' {
+        while !self.is_eof() && self.current() != close {
             if self.current().is_alphanumeric() || self.current() == '_' {
                 name.push(self.current());
                 self.advance();
             } else {
-                return Err(ParseError::InvalidGroupName);
+                return Err(self.error(ParseErrorKind::InvalidGroupName));
             }
         }
 
         if self.is_eof() || name.is_empty() {
-            return Err(ParseError::InvalidGroupName);
+            return Err(self.error(ParseErrorKind::InvalidGroupName));
         }
 
-        self.advance(); // consume '>'
+        self.advance(); // consume the closing delimiter
         Ok(name)
     }
 
     fn parse_escape(&mut self) -> Result<RegexNode, ParseError> {
+        let escape_start = self.position - 1; // the '\\' was already consumed
         if self.is_eof() {
-            return Err(ParseError::UnexpectedEndOfInput);
+            return Err(self.error(ParseErrorKind::UnexpectedEndOfInput));
         }
 
         match self.current() {
+            'b' if !self.dialect.supports(Feature::WordBoundary) => {
+                self.advance();
+                Err(self.unsupported(Feature::WordBoundary, escape_start))
+            }
+            'w' | 'W' | 'd' | 'D' | 's' | 'S' if !self.dialect.supports(Feature::CharacterType) => {
+                self.advance();
+                Err(self.unsupported(Feature::CharacterType, escape_start))
+            }
             'b' => {
                 self.advance();
                 Ok(RegexNode::WordBoundary)
             }
             'k' => {
                 self.advance();
-                if !self.check_char('<') {
-                    return Err(ParseError::InvalidBackreference);
+                if !self.dialect.supports(Feature::Backreference) {
+                    return Err(self.unsupported(Feature::Backreference, escape_start));
                 }
+                // \k<name>, or \k'name' and \k{name} in some engines; Python
+                // only has (?P=name)
+                let close = match self.peek() {
+                    Some('<') if self.dialect != Dialect::Python => '>',
+                    Some('\'') if matches!(self.dialect, Dialect::Pcre | Dialect::DotNet) => '\'',
+                    Some('{') if self.dialect == Dialect::Pcre => '}',
+                    _ => return Err(self.error(ParseErrorKind::InvalidBackreference)),
+                };
                 self.advance();
-                let name = self.parse_group_name()?;
+                let name = self.parse_group_name(close)?;
                 Ok(RegexNode::new_backreference(BackreferenceKind::NameBased(name)))
             }
+            // \g1, \g{1} or \g{name}
+            'g' if self.dialect == Dialect::Pcre => {
+                self.advance();
+                let braced = self.check_char('{');
+                if braced {
+                    self.advance();
+                }
+                match self.peek() {
+                    Some(c) if c.is_ascii_digit() => {
+                        let num = self.parse_backreference_number(escape_start)?;
+                        if braced {
+                            if !self.check_char('}') {
+                                return Err(self.error(ParseErrorKind::InvalidBackreference));
+                            }
+                            self.advance();
+                        }
+                        Ok(RegexNode::new_backreference(BackreferenceKind::NumberBased(num)))
+                    }
+                    Some(_) if braced => {
+                        let name = self.parse_group_name('}')?;
+                        Ok(RegexNode::new_backreference(BackreferenceKind::NameBased(name)))
+                    }
+                    _ => Err(self.error(ParseErrorKind::InvalidBackreference)),
+                }
+            }
             'w' => {
                 self.advance();
                 Ok(RegexNode::new_character_type(CharacterTypeKind::Word))
@@ -421,6 +823,9 @@ impl Parser {
             'p' | 'P' => {
                 let negated = self.current() == 'P';
                 self.advance();
+                if !self.dialect.supports(Feature::UnicodeCategory) {
+                    return Err(self.unsupported(Feature::UnicodeCategory, escape_start));
+                }
                 self.parse_unicode_category(negated)
             }
             'n' => {
@@ -454,40 +859,119 @@ impl Parser {
                 )))
             }
             '0' => {
+                self.advance();
+                let has_octal = !matches!(self.dialect, Dialect::Generic | Dialect::JavaScript);
+                if !has_octal || self.octal_digits_ahead() == 0 {
+                    return Ok(RegexNode::new_character_type(CharacterTypeKind::EscapedChar(
+                        EscapedChar::Null,
+                    )));
+                }
+                // Java takes a third digit as long as the value fits a byte
+                let max = if self.dialect == Dialect::Java
+                    && self.octal_digits_ahead() >= 3
+                    && self.current() <= '3'
+                {
+                    3
+                } else {
+                    2
+                };
+                let value = self.parse_octal(max);
+                Ok(RegexNode::new_character_type(CharacterTypeKind::EscapedChar(
+                    EscapedChar::Octal(value),
+                )))
+            }
+            // \o{17}
+            'o' if self.dialect == Dialect::Pcre => {
+                self.advance();
+                if !self.check_char('{') {
+                    return Err(self.error(ParseErrorKind::InvalidOctalNumber));
+                }
+                self.advance();
+                let digits = self.octal_digits_ahead();
+                let value = self.parse_octal(digits);
+                if digits == 0 || digits > 8 || !self.check_char('}') {
+                    return Err(self.error(ParseErrorKind::InvalidOctalNumber));
+                }
                 self.advance();
                 Ok(RegexNode::new_character_type(CharacterTypeKind::EscapedChar(
-                    EscapedChar::Null,
+                    EscapedChar::Octal(value),
                 )))
             }
             'x' => {
                 self.advance();
-                let hex_value = self.parse_hex(2)?;
+                // \x{1F600}
+                let braced = self.check_char('{')
+                    && matches!(self.dialect, Dialect::Pcre | Dialect::Java | Dialect::Re2);
+                let hex_value = if braced {
+                    self.parse_braced_unicode_value(escape_start)?
+                } else {
+                    self.parse_hex(2)?
+                };
                 Ok(RegexNode::new_character_type(CharacterTypeKind::EscapedChar(
                     EscapedChar::Hex(hex_value),
                 )))
             }
             'u' => {
                 self.advance();
-                if !self.check_char('{') {
-                    return Err(ParseError::InvalidUnicodeValue);
+                if !self.dialect.supports(Feature::UnicodeEscape) {
+                    return Err(self.unsupported(Feature::UnicodeEscape, escape_start));
                 }
+                let hex_value = if self.check_char('{')
+                    && matches!(self.dialect, Dialect::Generic | Dialect::JavaScript)
+                {
+                    self.parse_braced_unicode_value(escape_start)?
+                } else if self.dialect != Dialect::Generic {
+                    // \uHHHH
+                    self.parse_hex(4)?
+                } else {
+                    return Err(self.error(ParseErrorKind::InvalidUnicodeValue));
+                };
+                Ok(RegexNode::new_character_type(CharacterTypeKind::EscapedChar(
+                    EscapedChar::Unicode(hex_value),
+                )))
+            }
+            // \UHHHHHHHH
+            'U' if self.dialect == Dialect::Python => {
                 self.advance();
-                let hex_value = self.parse_unicode_value()?;
-                if !self.check_char('}') {
-                    return Err(ParseError::InvalidUnicodeValue);
+                let hex_value = self.parse_hex(8)?;
+                if char::from_u32(hex_value).is_none() {
+                    return Err(self.error_span(
+                        ParseErrorKind::InvalidUnicodeValue,
+                        escape_start,
+                        self.position,
+                    ));
                 }
-                self.advance();
                 Ok(RegexNode::new_character_type(CharacterTypeKind::EscapedChar(
                     EscapedChar::Unicode(hex_value),
                 )))
             }
             c if c.is_ascii_digit() => {
-                let num = self.parse_number()?;
-                if num == 0 || num > self.group_count {
-                    return Err(ParseError::InvalidBackreference);
+                // Three octal digits are a char in Python, and RE2 reads any
+                // escaped digits past the first as octal
+                let octal = match self.dialect {
+                    Dialect::Python => self.octal_digits_ahead() >= 3,
+                    Dialect::Re2 => self.octal_digits_ahead() >= 2,
+                    _ => false,
+                };
+                if octal {
+                    let value = self.parse_octal(3);
+                    return Ok(RegexNode::new_character_type(CharacterTypeKind::EscapedChar(
+                        EscapedChar::Octal(value),
+                    )));
+                }
+                if !self.dialect.supports(Feature::Backreference) {
+                    let _ = self.parse_number();
+                    return Err(self.unsupported(Feature::Backreference, escape_start));
                 }
+
+                let num = self.parse_backreference_number(escape_start)?;
                 Ok(RegexNode::new_backreference(BackreferenceKind::NumberBased(num)))
             }
+            // Reading these as literals would quietly change the pattern
+            c if c.is_ascii_alphabetic() => {
+                self.advance();
+                Err(self.error_span(ParseErrorKind::InvalidEscape, escape_start, self.position))
+            }
             c => {
                 self.advance();
                 Ok(RegexNode::new_literal(c))
@@ -496,27 +980,32 @@ impl Parser {
     }
 
     fn parse_unicode_category(&mut self, negated: bool) -> Result<RegexNode, ParseError> {
-        if !self.check_char('{') {
-            return Err(ParseError::InvalidUnicodeCategory);
+        // Some engines also take a single letter without braces, as in `\pL`
+        let braced = self.check_char('{');
+        if braced {
+            self.advance();
+        } else if !matches!(self.dialect, Dialect::Pcre | Dialect::Java | Dialect::Re2) {
+            return Err(self.error(ParseErrorKind::InvalidUnicodeCategory));
         }
-        self.advance();
 
-        let category = match self.current() {
-            'L' => UnicodeCategoryKind::Letter,
-            'N' => UnicodeCategoryKind::Number,
-            'P' => UnicodeCategoryKind::Punctuation,
-            'S' => UnicodeCategoryKind::Symbol,
-            'M' => UnicodeCategoryKind::Mark,
-            'Z' => UnicodeCategoryKind::Separator,
-            'C' => UnicodeCategoryKind::Other,
-            _ => return Err(ParseError::InvalidUnicodeCategory),
+        let category = match self.peek() {
+            Some('L') => UnicodeCategoryKind::Letter,
+            Some('N') => UnicodeCategoryKind::Number,
+            Some('P') => UnicodeCategoryKind::Punctuation,
+            Some('S') => UnicodeCategoryKind::Symbol,
+            Some('M') => UnicodeCategoryKind::Mark,
+            Some('Z') => UnicodeCategoryKind::Separator,
+            Some('C') => UnicodeCategoryKind::Other,
+            _ => return Err(self.error(ParseErrorKind::InvalidUnicodeCategory)),
         };
         self.advance();
 
-        if !self.check_char('}') {
-            return Err(ParseError::InvalidUnicodeCategory);
+        if braced {
+            if !self.check_char('}') {
+                return Err(self.error(ParseErrorKind::InvalidUnicodeCategory));
+            }
+            self.advance();
         }
-        self.advance();
 
         Ok(RegexNode::new_unicode_category(category, negated))
     }
@@ -525,10 +1014,10 @@ impl Parser {
         let mut value = 0;
         for _ in 0..count {
             if self.is_eof() {
-                return Err(ParseError::InvalidHexNumber);
+                return Err(self.error(ParseErrorKind::InvalidHexNumber));
             }
             let digit = self.current().to_digit(16)
-                .ok_or(ParseError::InvalidHexNumber)?;
+                .ok_or_else(|| self.error(ParseErrorKind::InvalidHexNumber))?;
             value = value * 16 + digit;
             self.advance();
         }
@@ -540,42 +1029,115 @@ impl Parser {
         let mut count = 0;
         while !self.is_eof() && self.current() != '}' && count < 6 {
             let digit = self.current().to_digit(16)
-                .ok_or(ParseError::InvalidUnicodeValue)?;
+                .ok_or_else(|| self.error(ParseErrorKind::InvalidUnicodeValue))?;
             value = value * 16 + digit;
             self.advance();
             count += 1;
         }
         if count == 0 {
-            return Err(ParseError::InvalidUnicodeValue);
+            return Err(self.error(ParseErrorKind::InvalidUnicodeValue));
         }
         Ok(value)
     }
 
-    fn parse_number(&mut self) -> Result<usize, ParseError> {
-        let mut num = 0;
-        while !self.is_eof() && self.current().is_ascii_digit() {
-            num = num * 10 + self.current().to_digit(10).unwrap() as usize;
-            self.advance();
+    /// Reads `{...}` with the hex value of a char in it, which must be one:
+    /// at most 0x10FFFF and not a surrogate.
+    fn parse_braced_unicode_value(&mut self, escape_start: usize) -> Result<u32, ParseError> {
+        self.advance(); // consume '{'
+        let value = self.parse_unicode_value()?;
+        if !self.check_char('}') {
+            return Err(self.error(ParseErrorKind::InvalidUnicodeValue));
         }
-        Ok(num)
+        self.advance();
+        if char::from_u32(value).is_none() {
+            return Err(self.error_span(ParseErrorKind::InvalidUnicodeValue, escape_start, self.position));
+        }
+        Ok(value)
     }
 
-    fn check_str(&mut self, s: &str) -> bool {
-        let chars: Vec<char> = s.chars().collect();
-        let mut pos = self.position;
-        
-        for &c in &chars {
-            if pos >= self.input.len() || self.input[pos] != c {
-                return false;
+    /// Reads up to `max` octal digits.
+    fn parse_octal(&mut self, max: usize) -> u32 {
+        let mut value = 0;
+        for _ in 0..max {
+            match self.peek().and_then(|c| c.to_digit(8)) {
+                Some(digit) => value = value * 8 + digit,
+                None => break,
             }
-            pos += 1;
+            self.advance();
+        }
+        value
+    }
+
+    fn octal_digits_ahead(&self) -> usize {
+        self.input[self.position..]
+            .iter()
+            .take_while(|c| c.is_digit(8))
+            .count()
+    }
+
+    /// Reads the number of a backreference, which must name an earlier group.
+    fn parse_backreference_number(&mut self, escape_start: usize) -> Result<usize, ParseError> {
+        // A number too big to read can't be a group's either
+        let num = self.parse_number().unwrap_or(0);
+        // Which group .NET means is only known once all of them are read
+        let opened = self.dialect == Dialect::DotNet || num <= self.group_count;
+        if num == 0 || !opened {
+            return Err(self.error_span(
+                ParseErrorKind::InvalidBackreference,
+                escape_start,
+                self.position,
+            ));
+        }
+        if self.dialect == Dialect::DotNet {
+            self.backreference_spans.push(Span { start: escape_start, end: self.position });
         }
+        Ok(num)
+    }
 
-        // If we matched the string, advance the position
-        for _ in 0..chars.len() {
+    /// Reads a decimal number, every digit of it even when it is too big.
+    fn parse_number(&mut self) -> Result<usize, ParseError> {
+        let start = self.position;
+        let mut num = Some(0usize);
+        while !self.is_eof() && self.current().is_ascii_digit() {
+            let digit = self.current().to_digit(10).unwrap() as usize;
+            num = num.and_then(|num| num.checked_mul(10)?.checked_add(digit));
             self.advance();
         }
-        true
+        num.ok_or_else(|| self.error_span(ParseErrorKind::InvalidNumber, start, self.position))
+    }
+
+    /// Builds an error pointing at the current character, or just past the
+    /// end of the pattern once the input is exhausted.
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        let end = (self.position + 1).min(self.input.len()).max(self.position);
+        self.error_span(kind, self.position, end)
+    }
+
+    /// Builds an error pointing at a single earlier character, such as the
+    /// `(` of a group that never gets closed.
+    fn error_at(&self, kind: ParseErrorKind, start: usize) -> ParseError {
+        self.error_span(kind, start, start + 1)
+    }
+
+    /// Builds an error for a construct the dialect lacks, spanning it from
+    /// `start` to the current position.
+    fn unsupported(&self, feature: Feature, start: usize) -> ParseError {
+        self.error_span(ParseErrorKind::Unsupported(feature, self.dialect), start, self.position)
+    }
+
+    fn error_span(&self, kind: ParseErrorKind, start: usize, end: usize) -> ParseError {
+        let byte_offset = |pos: usize| -> usize {
+            self.input[..pos.min(self.input.len())].iter().map(|c| c.len_utf8()).sum()
+        };
+        ParseError {
+            expected: kind.expected(),
+            kind,
+            span: Span { start, end },
+            byte_span: Span {
+                start: byte_offset(start),
+                end: byte_offset(end),
+            },
+        }
     }
 
     fn check_char(&self, c: char) -> bool {
@@ -586,6 +1148,10 @@ impl Parser {
         self.input[self.position]
     }
 
+    fn peek(&self) -> Option<char> {
+        self.input.get(self.position).copied()
+    }
+
     fn advance(&mut self) {
         self.position += 1;
     }
@@ -593,4 +1159,33 @@ impl Parser {
     fn is_eof(&self) -> bool {
         self.position >= self.input.len()
     }
+} 
+
+/// The change from `from` to `to` in the flags that affect matching; the
+/// others only change how the pattern is read, which the tree already shows.
+fn matching_flag_change(from: &RegexFlags, to: &RegexFlags) -> FlagChange {
+    let matching = |flags: &RegexFlags| RegexFlags {
+        case_insensitive: flags.case_insensitive,
+        multiline: flags.multiline,
+        dot_all: flags.dot_all,
+        unicode: flags.unicode,
+        ..RegexFlags::new()
+    };
+    FlagChange::between(&matching(from), &matching(to))
+}
+
+/// Puts an alternative under the flags carried into it from the ones before.
+/// An alternative that is just an unscoped flag group gets one group for
+/// both, so that printing and reparsing it gives back the same tree.
+fn carry_flags(carried: FlagChange, nodes: Vec<RegexNode>) -> Vec<RegexNode> {
+    if carried.is_empty() || nodes.is_empty() {
+        return nodes;
+    }
+    match <[RegexNode; 1]>::try_from(nodes) {
+        Ok([RegexNode::FlagSet(change, FlagScope::Unscoped, nodes)]) => {
+            vec![RegexNode::new_flag_set(carried.then(&change), nodes)]
+        }
+        Ok([node]) => vec![RegexNode::new_flag_set(carried, vec![node])],
+        Err(nodes) => vec![RegexNode::new_flag_set(carried, nodes)],
+    }
 } 
\ No newline at end of file
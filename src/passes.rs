@@ -0,0 +1,330 @@
+//! The obfuscation passes the default pipeline is made of.
+
+use crate::ast::{
+    RegexNode, GroupKind, ClassItem, CharacterTypeKind, EscapedChar, LookaroundKind, Quantifier,
+    QuantifierMode, AnchorType,
+};
+use crate::obfuscator::{Pass, PassContext};
+use rand::Rng;
+
+/// Turns a character class into an alternation of single-item classes,
+/// splitting ranges at random points: `[a-z0]` → `(?:[a-m]|[n-z]|[0])`.
+pub struct ClassToAlternation;
+
+/// Wraps each literal in a class of its own: `a` → `[a]`.
+pub struct LiteralToClass;
+
+/// Rewrites `.` into one of several equivalent spellings, depending on
+/// whether dot-all is active.
+pub struct DotRewrite;
+
+/// Rewrites a quantified node into an equivalent, randomly chosen form,
+/// e.g. `a*` → `a{0,}` or `a{3}` → `(?:aa{2})`. Laziness is carried over
+/// to every quantifier that can still backtrack, and possessive quantifiers
+/// become atomic groups: `a++` → `(?>a+)`.
+pub struct QuantifierRewrite;
+
+/// Gives unnamed capturing groups random names and wraps groups and
+/// backreferences in non-capturing groups: `(abc)` → `(?:(?<k3Fq9x>abc))`.
+/// How many groups may be stacked is set by the obfuscator's wrap depth.
+pub struct GroupRewrite;
+
+/// Inserts assertions that never change what matches, such as
+/// `(?=[\s\S]|$)` between nodes or `(?=a)` before a literal `a`.
+pub struct LookaroundNoise;
+
+impl Pass for ClassToAlternation {
+    fn name(&self) -> &str {
+        "class-alternation"
+    }
+
+    fn rewrite(&mut self, node: RegexNode, ctx: &mut PassContext) -> RegexNode {
+        let RegexNode::CharacterClass { negated: false, items } = node else {
+            // Negated (and never-matching empty) classes are kept as is for now
+            return node;
+        };
+        if items.is_empty() || !ctx.chance() {
+            return RegexNode::new_class(items, false);
+        }
+
+        let items: Vec<ClassItem> = items
+            .into_iter()
+            .flat_map(|item| split_class_item(item, ctx))
+            .collect();
+        if items.len() == 1 {
+            return RegexNode::new_class(items, false);
+        }
+
+        let alternatives = items
+            .into_iter()
+            .map(|item| vec![RegexNode::new_class(vec![item], false)])
+            .collect();
+        RegexNode::new_group(GroupKind::NonCapturing, vec![RegexNode::new_alternation(alternatives)])
+    }
+}
+
+/// Splits a range at a random point so `a-z` may become `a-m` and `n-z`.
+/// Every other item is passed through unchanged.
+fn split_class_item(item: ClassItem, ctx: &mut PassContext) -> Vec<ClassItem> {
+    match item {
+        ClassItem::Range { start, end } if (end as u32) > (start as u32) => {
+            let mid = ctx.rng().gen_range(start as u32..end as u32);
+            // Splitting across the surrogate gap would produce invalid chars
+            match (char::from_u32(mid), char::from_u32(mid + 1)) {
+                (Some(left_end), Some(right_start)) => vec![
+                    ClassItem::new_range(start, left_end),
+                    ClassItem::new_range(right_start, end),
+                ],
+                _ => vec![ClassItem::new_range(start, end)],
+            }
+        }
+        item => vec![item],
+    }
+}
+
+impl Pass for LiteralToClass {
+    fn name(&self) -> &str {
+        "literal-class"
+    }
+
+    fn rewrite(&mut self, node: RegexNode, ctx: &mut PassContext) -> RegexNode {
+        match node {
+            RegexNode::Literal(c) if ctx.chance() => RegexNode::new_char_class(vec![c], false),
+            node => node,
+        }
+    }
+}
+
+impl Pass for DotRewrite {
+    fn name(&self) -> &str {
+        "dot"
+    }
+
+    fn rewrite(&mut self, node: RegexNode, ctx: &mut PassContext) -> RegexNode {
+        if !matches!(node, RegexNode::Dot) || !ctx.chance() {
+            return node;
+        }
+
+        let newline = || {
+            RegexNode::new_character_type(CharacterTypeKind::EscapedChar(EscapedChar::NewLine))
+        };
+        let not_newline = |esc: EscapedChar| RegexNode::new_class(vec![ClassItem::Escaped(esc)], true);
+        let any_of = |a: CharacterTypeKind, b: CharacterTypeKind| {
+            RegexNode::new_class(vec![ClassItem::CharacterType(a), ClassItem::CharacterType(b)], false)
+        };
+
+        if ctx.flags().dot_all {
+            match ctx.rng().gen_range(0..4) {
+                // [\s\S]
+                0 => any_of(CharacterTypeKind::Whitespace, CharacterTypeKind::NotWhitespace),
+                // [\d\D]
+                1 => any_of(CharacterTypeKind::Digit, CharacterTypeKind::NotDigit),
+                // [\w\W]
+                2 => any_of(CharacterTypeKind::Word, CharacterTypeKind::NotWord),
+                // (?:\n|[^\n])
+                _ => RegexNode::new_group(
+                    GroupKind::NonCapturing,
+                    vec![RegexNode::new_alternation(vec![
+                        vec![newline()],
+                        vec![not_newline(EscapedChar::NewLine)],
+                    ])],
+                ),
+            }
+        } else {
+            match ctx.rng().gen_range(0..3) {
+                // [^\n]
+                0 => not_newline(EscapedChar::NewLine),
+                // [^\x0A]
+                1 => not_newline(EscapedChar::Hex(0x0A)),
+                // (?:(?!\n)[\s\S])
+                _ => RegexNode::new_group(
+                    GroupKind::NonCapturing,
+                    vec![
+                        RegexNode::new_lookaround(LookaroundKind::NegativeLookahead, vec![newline()]),
+                        any_of(CharacterTypeKind::Whitespace, CharacterTypeKind::NotWhitespace),
+                    ],
+                ),
+            }
+        }
+    }
+}
+
+impl Pass for QuantifierRewrite {
+    fn name(&self) -> &str {
+        "quantifier"
+    }
+
+    fn rewrite(&mut self, node: RegexNode, ctx: &mut PassContext) -> RegexNode {
+        let RegexNode::Quantified { node, quantifier } = node else {
+            return node;
+        };
+        let node = *node;
+        if !ctx.chance() {
+            return node.with_quantifier(quantifier);
+        }
+
+        // X*+ → (?>X*), the greedy loop being respelled like any other
+        if quantifier.mode() == QuantifierMode::Possessive {
+            let greedy = respell(node, quantifier.with_mode(QuantifierMode::Greedy), ctx);
+            return RegexNode::new_group(GroupKind::Atomic, vec![greedy]);
+        }
+        respell(node, quantifier, ctx)
+    }
+}
+
+/// Spells a greedy or lazy quantifier one of several equivalent ways.
+fn respell(node: RegexNode, quantifier: Quantifier, ctx: &mut PassContext) -> RegexNode {
+    // Repeating the node by hand would add capture groups
+    let can_repeat = !node.has_capturing_group();
+    let sequence = |nodes: Vec<RegexNode>| RegexNode::new_group(GroupKind::NonCapturing, nodes);
+
+    match quantifier {
+        // X* → X{0,} | (?:X+)?
+        Quantifier::ZeroOrMore { mode } => match ctx.rng().gen_range(0..2) {
+            0 => node.with_quantifier(Quantifier::AtLeast { min: 0, mode }),
+            _ => sequence(vec![node.with_quantifier(Quantifier::OneOrMore { mode })])
+                .with_quantifier(Quantifier::ZeroOrOne { mode }),
+        },
+        // X+ → X{1,} | (?:XX*)
+        Quantifier::OneOrMore { mode } => match ctx.rng().gen_range(0..2) {
+            0 if can_repeat => sequence(vec![
+                node.clone(),
+                node.with_quantifier(Quantifier::ZeroOrMore { mode }),
+            ]),
+            _ => node.with_quantifier(Quantifier::AtLeast { min: 1, mode }),
+        },
+        // X? → X{0,1}
+        Quantifier::ZeroOrOne { mode } => {
+            node.with_quantifier(Quantifier::Range { min: 0, max: 1, mode })
+        }
+        Quantifier::Exactly(0) => node.with_quantifier(quantifier),
+        // X{n} → X{n,n} | (?:X{1}){n} | (?:XX{n-1})
+        Quantifier::Exactly(n) => match ctx.rng().gen_range(0..3) {
+            0 => node.with_quantifier(Quantifier::Range { min: n, max: n, mode: QuantifierMode::Greedy }),
+            1 if can_repeat && n > 1 => sequence(vec![
+                node.clone(),
+                node.with_quantifier(Quantifier::Exactly(n - 1)),
+            ]),
+            _ => sequence(vec![node.with_quantifier(Quantifier::Exactly(1))])
+                .with_quantifier(Quantifier::Exactly(n)),
+        },
+        // X{n,} → (?:X{n}X*)
+        Quantifier::AtLeast { min, mode } if can_repeat && min > 0 => sequence(vec![
+            node.clone().with_quantifier(Quantifier::Exactly(min)),
+            node.with_quantifier(Quantifier::ZeroOrMore { mode }),
+        ]),
+        // X{n,m} → (?:X{n}X{0,m-n})
+        Quantifier::Range { min, max, mode } if can_repeat && min > 0 && max > min => {
+            sequence(vec![
+                node.clone().with_quantifier(Quantifier::Exactly(min)),
+                node.with_quantifier(Quantifier::Range { min: 0, max: max - min, mode }),
+            ])
+        }
+        _ => node.with_quantifier(quantifier),
+    }
+}
+
+impl Pass for GroupRewrite {
+    fn name(&self) -> &str {
+        "group"
+    }
+
+    fn rewrite(&mut self, node: RegexNode, ctx: &mut PassContext) -> RegexNode {
+        match node {
+            RegexNode::Group(GroupKind::Capturing(name), nodes) if ctx.chance() => {
+                if let Some(name) = &name {
+                    if ctx.is_unnamed_capture(name) && ctx.rng().gen_bool(0.5) {
+                        ctx.name_capture(name);
+                    }
+                }
+
+                wrap(RegexNode::new_group(GroupKind::Capturing(name), nodes), ctx)
+            }
+            // (?:\1)
+            RegexNode::Backreference(kind) if ctx.chance() => {
+                wrap(RegexNode::Backreference(kind), ctx)
+            }
+            node => node,
+        }
+    }
+}
+
+/// Wraps a node in non-capturing groups, each with even odds, up to the
+/// context's wrapping depth: `(a)` → `(a)`, `(?:(a))`, `(?:(?:(a)))`...
+fn wrap(mut node: RegexNode, ctx: &mut PassContext) -> RegexNode {
+    for _ in 0..ctx.wrap_depth() {
+        if !ctx.rng().gen_bool(0.5) {
+            break;
+        }
+        node = RegexNode::new_group(GroupKind::NonCapturing, vec![node]);
+    }
+    node
+}
+
+impl Pass for LookaroundNoise {
+    fn name(&self) -> &str {
+        "lookaround-noise"
+    }
+
+    fn rewrite(&mut self, node: RegexNode, ctx: &mut PassContext) -> RegexNode {
+        // Assertions nested in lookarounds are not supported everywhere
+        // (lookbehinds in particular), so their bodies are left alone
+        let is_single_char = match &node {
+            RegexNode::Literal(_) => true,
+            RegexNode::CharacterClass { negated: false, items } => {
+                items.len() == 1 && items[0].as_char().is_some()
+            }
+            _ => false,
+        };
+        if !is_single_char || ctx.in_lookaround() || !ctx.chance() {
+            return node;
+        }
+
+        // (?=a)a
+        let lookahead =
+            RegexNode::new_lookaround(LookaroundKind::PositiveLookahead, vec![node.clone()]);
+        RegexNode::new_group(GroupKind::NonCapturing, vec![lookahead, node])
+    }
+
+    fn insert_before(&mut self, _next: &RegexNode, ctx: &mut PassContext) -> Option<RegexNode> {
+        if ctx.in_lookaround() || !ctx.chance() {
+            return None;
+        }
+        Some(neutral_assertion(ctx))
+    }
+}
+
+/// An assertion that holds at every position in every string.
+fn neutral_assertion(ctx: &mut PassContext) -> RegexNode {
+    // [\s\S], or [^\s\S] which never matches
+    let any_char = |negated: bool| {
+        RegexNode::new_class(
+            vec![
+                ClassItem::CharacterType(CharacterTypeKind::Whitespace),
+                ClassItem::CharacterType(CharacterTypeKind::NotWhitespace),
+            ],
+            negated,
+        )
+    };
+    let any_char_or = |anchor: AnchorType| {
+        vec![RegexNode::new_alternation(vec![
+            vec![any_char(false)],
+            vec![RegexNode::new_anchor(anchor)],
+        ])]
+    };
+
+    match ctx.rng().gen_range(0..3) {
+        // (?=[\s\S]|$)
+        0 => RegexNode::new_lookaround(
+            LookaroundKind::PositiveLookahead,
+            any_char_or(AnchorType::End),
+        ),
+        // (?<=[\s\S]|^)
+        1 => RegexNode::new_lookaround(
+            LookaroundKind::PositiveLookbehind,
+            any_char_or(AnchorType::Start),
+        ),
+        // (?![^\s\S])
+        _ => RegexNode::new_lookaround(LookaroundKind::NegativeLookahead, vec![any_char(true)]),
+    }
+}
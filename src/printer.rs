@@ -1,95 +1,463 @@
-use crate::ast::{RegexNode, GroupKind, Quantifier, CharacterTypeKind, EscapedChar, AnchorType};
+use std::fmt;
+
+use crate::ast::{
+    self, RegexNode, GroupKind, Quantifier, QuantifierMode, CharacterTypeKind, ClassItem, EscapedChar, AnchorType,
+    UnicodeCategoryKind, BackreferenceKind, LookaroundKind, RegexFlags, FlagChange, FlagScope,
+};
+use crate::dialect::{Dialect, Feature};
 
 pub struct Printer {
     use_unicode_escapes: bool,
+    dialect: Dialect,
+}
+
+/// Where a character is being printed, which decides what must be escaped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CharContext {
+    TopLevel,
+    Class { first: bool, last: bool },
 }
 
+/// The tree uses something the target dialect has no way to write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintError {
+    pub feature: Feature,
+    pub dialect: Dialect,
+}
+
+impl fmt::Display for PrintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} are not supported in {}", self.feature, self.dialect)
+    }
+}
+
+impl std::error::Error for PrintError {}
+
+type PrintResult = Result<String, PrintError>;
+
 impl Printer {
     pub fn new(use_unicode_escapes: bool) -> Self {
-        Printer { use_unicode_escapes }
+        Printer {
+            use_unicode_escapes,
+            dialect: Dialect::Generic,
+        }
+    }
+
+    /// Prints in the syntax of the given engine, picking the escapes and the
+    /// group and backreference forms it understands.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
     }
 
+    /// Prints the tree, panicking if the target dialect can't express it.
+    /// The generic dialect expresses everything; for any other, prefer
+    /// [`try_print`](Self::try_print).
     pub fn print(&self, ast: &[RegexNode]) -> String {
-        ast.iter()
-            .map(|node| self.print_node(node))
-            .collect::<Vec<_>>()
-            .join("")
-    }
-
-    fn print_node(&self, node: &RegexNode) -> String {
-        match node {
-            RegexNode::Literal(c) => self.print_char(*c),
-            RegexNode::CharacterClass { negated, chars } => {
-                let mut result = String::from("[");
-                if *negated {
-                    result.push('^');
+        self.try_print(ast).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_print(&self, ast: &[RegexNode]) -> PrintResult {
+        let order = self.dialect.group_order(ast);
+        if order.iter().enumerate().all(|(i, &position)| position == i + 1) {
+            return self.print_sequence(ast);
+        }
+
+        // Backreferences count groups as they open; .NET counts differently
+        let mut ast = ast.to_vec();
+        ast::walk_mut(&mut ast, &mut |node| {
+            if let RegexNode::Backreference(BackreferenceKind::NumberBased(n)) = node {
+                if let Some(i) = order.iter().position(|&position| position == *n) {
+                    *n = i + 1;
                 }
-                result.push_str(
-                    &chars
-                        .iter()
-                        .map(|c| self.print_char(*c))
-                        .collect::<Vec<_>>()
-                        .join(""),
-                );
-                result.push(']');
-                result
             }
+        });
+        self.print_sequence(&ast)
+    }
+
+    fn print_sequence(&self, ast: &[RegexNode]) -> PrintResult {
+        let mut printed = ast
+            .iter()
+            .map(|node| match node {
+                // Next to other nodes, `a|b` would take them into its branches
+                RegexNode::Alternation(_) if ast.len() > 1 => {
+                    self.require(Feature::NonCapturingGroup)?;
+                    Ok(format!("(?:{})", self.print_node(node)?))
+                }
+                _ => self.print_node(node),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // `\1` directly followed by a digit would read back as `\10`
+        for i in 1..printed.len() {
+            let after_backreference = matches!(
+                ast[i - 1],
+                RegexNode::Backreference(BackreferenceKind::NumberBased(_))
+            );
+            if after_backreference && printed[i].starts_with(|c: char| c.is_ascii_digit()) {
+                printed[i - 1] = format!("(?:{})", printed[i - 1]);
+            }
+        }
+
+        Ok(printed.join(""))
+    }
+
+    fn print_node(&self, node: &RegexNode) -> PrintResult {
+        let printed = match node {
+            RegexNode::Literal(c) => self.print_char(*c, CharContext::TopLevel)?,
+            RegexNode::CharacterClass { negated, items } => self.print_class(items, *negated)?,
             RegexNode::Dot => ".".to_string(),
             RegexNode::Anchor(anchor_type) => match anchor_type {
                 AnchorType::Start => "^".to_string(),
                 AnchorType::End => "$".to_string(),
             },
-            RegexNode::WordBoundary => "\\b".to_string(),
+            RegexNode::WordBoundary => {
+                self.require(Feature::WordBoundary)?;
+                "\\b".to_string()
+            }
             RegexNode::Quantified { node, quantifier } => {
-                format!("{}{}", self.print_node(node), self.print_quantifier(quantifier))
+                format!("{}{}", self.print_node(node)?, self.print_quantifier(quantifier)?)
             }
             RegexNode::Group(kind, nodes) => {
-                let contents = self.print(nodes);
+                let contents = self.print_sequence(nodes)?;
                 match kind {
                     GroupKind::Capturing(None) => format!("({})", contents),
-                    GroupKind::Capturing(Some(name)) => format!("(?<{}>{})", name, contents),
-                    GroupKind::NonCapturing => format!("(?:{})", contents),
+                    GroupKind::Capturing(Some(name)) => {
+                        self.require(Feature::NamedGroup)?;
+                        match self.dialect {
+                            Dialect::Python | Dialect::Re2 => format!("(?P<{}>{})", name, contents),
+                            _ => format!("(?<{}>{})", name, contents),
+                        }
+                    }
+                    GroupKind::NonCapturing => {
+                        self.require(Feature::NonCapturingGroup)?;
+                        format!("(?:{})", contents)
+                    }
+                    GroupKind::Atomic => {
+                        self.require(Feature::AtomicGroup)?;
+                        format!("(?>{})", contents)
+                    }
                 }
             }
             RegexNode::Alternation(alternatives) => alternatives
                 .iter()
-                .map(|alt| self.print(alt))
-                .collect::<Vec<_>>()
+                .map(|alt| self.print_sequence(alt))
+                .collect::<Result<Vec<_>, _>>()?
                 .join("|"),
-            RegexNode::CharacterType(char_type) => match char_type {
-                CharacterTypeKind::Word => "\\w".to_string(),
-                CharacterTypeKind::NotWord => "\\W".to_string(),
-                CharacterTypeKind::Digit => "\\d".to_string(),
-                CharacterTypeKind::NotDigit => "\\D".to_string(),
-                CharacterTypeKind::Whitespace => "\\s".to_string(),
-                CharacterTypeKind::NotWhitespace => "\\S".to_string(),
-                CharacterTypeKind::EscapedChar(esc) => self.print_escaped_char(esc),
-            },
-            // Add other cases as needed
-            _ => String::new(),
+            RegexNode::CharacterType(char_type) => self.print_character_type(char_type)?,
+            RegexNode::Backreference(kind) => {
+                self.require(Feature::Backreference)?;
+                match kind {
+                    BackreferenceKind::NumberBased(n) => format!("\\{}", n),
+                    BackreferenceKind::NameBased(name) if self.dialect == Dialect::Python => {
+                        format!("(?P={})", name)
+                    }
+                    BackreferenceKind::NameBased(name) => format!("\\k<{}>", name),
+                }
+            }
+            RegexNode::UnicodeCategory { negated, category } => {
+                self.print_unicode_category(category, *negated)?
+            }
+            RegexNode::Lookaround(kind, nodes) => {
+                let prefix = match kind {
+                    LookaroundKind::PositiveLookahead => "?=",
+                    LookaroundKind::NegativeLookahead => "?!",
+                    LookaroundKind::PositiveLookbehind => "?<=",
+                    LookaroundKind::NegativeLookbehind => "?<!",
+                };
+                match kind {
+                    LookaroundKind::PositiveLookahead | LookaroundKind::NegativeLookahead => {
+                        self.require(Feature::Lookahead)?
+                    }
+                    _ => self.dialect.check_lookbehind(nodes).map_err(|feature| self.error(feature))?,
+                }
+                format!("({}{})", prefix, self.print_sequence(nodes)?)
+            }
+            RegexNode::FlagSet(change, scope, nodes) => {
+                self.require(Feature::InlineFlags)?;
+                let flags = self.print_flags(change);
+                let contents = self.print_sequence(nodes)?;
+                match scope {
+                    // No flag is left to print, but the group still bounds
+                    // what it covers
+                    _ if flags.is_empty() => {
+                        self.require(Feature::NonCapturingGroup)?;
+                        format!("(?:{})", contents)
+                    }
+                    // Where flags can't apply to the rest of the group, they
+                    // are scoped to the nodes they cover, which is the same
+                    FlagScope::Unscoped if self.dialect.supports(Feature::UnscopedFlags) => {
+                        // Unscoped flags apply to everything that follows them
+                        format!("(?{}){}", flags, contents)
+                    }
+                    _ => format!("(?{}:{})", flags, contents),
+                }
+            }
+        };
+        Ok(printed)
+    }
+
+    /// Prints a flag change as `ims-ims`. `x`, `U` and `n` only change how
+    /// the pattern is read, which the tree already reflects, so they are
+    /// left out, as is `u` where the dialect doesn't know it.
+    fn print_flags(&self, change: &FlagChange) -> String {
+        let letters = |flags: &RegexFlags| -> String {
+            flags
+                .to_chars()
+                .into_iter()
+                .filter(|&c| "imsu".contains(c) && self.dialect.inline_flags().contains(c))
+                .collect()
+        };
+        let (set, clear) = (letters(&change.set), letters(&change.clear));
+        if clear.is_empty() {
+            set
+        } else {
+            format!("{}-{}", set, clear)
+        }
+    }
+
+    fn print_class(&self, items: &[ClassItem], negated: bool) -> PrintResult {
+        let mut result = String::from("[");
+        if negated {
+            result.push('^');
+        }
+        if self.dialect == Dialect::PosixEre {
+            let body = self.print_posix_class_items(items)?;
+            // `[^]` would be a negation in POSIX, which can escape `^`
+            // outside a class only
+            if !negated && body == "^" {
+                return Ok("\\^".to_string());
+            }
+            result.push_str(&body);
+        } else {
+            for (i, item) in items.iter().enumerate() {
+                result.push_str(&self.print_class_item(item, i == 0, i + 1 == items.len())?);
+            }
+        }
+        result.push(']');
+        Ok(result)
+    }
+
+    /// POSIX bracket expressions have no escapes: `]` is only literal first,
+    /// `-` first or last and `^` anywhere but first, so items get reordered.
+    fn print_posix_class_items(&self, items: &[ClassItem]) -> PrintResult {
+        let single = |c: char| items.iter().any(|item| item.as_char() == Some(c));
+        let mut printed = String::new();
+        if single(']') {
+            printed.push(']');
+        }
+        let mut caret = single('^');
+        for item in items {
+            match item {
+                _ if matches!(item.as_char(), Some(']' | '^' | '[' | '-')) => {}
+                // `^-a` would negate if it came first, so the `^` is split off
+                ClassItem::Range { start: '^', end } => {
+                    caret = true;
+                    let rest = match *end {
+                        '^' => continue,
+                        '_' => ClassItem::Char('_'),
+                        end => ClassItem::new_range('_', end),
+                    };
+                    printed.push_str(&self.print_class_item(&rest, false, false)?);
+                }
+                _ => printed.push_str(&self.print_class_item(item, false, false)?),
+            }
+        }
+        // Kept away from the start, and `[` from a following `:`, `.` or `=`;
+        // with nothing else to lead, `-` goes first
+        let trailing = if printed.is_empty() { ['-', '[', '^'] } else { ['^', '[', '-'] };
+        for c in trailing {
+            if (c == '^' && caret) || (c != '^' && single(c)) {
+                printed.push(c);
+            }
+        }
+        Ok(printed)
+    }
+
+    fn print_class_item(&self, item: &ClassItem, first: bool, last: bool) -> PrintResult {
+        match item {
+            ClassItem::Char(c) => self.print_char(*c, CharContext::Class { first, last }),
+            ClassItem::Range { start, end } => Ok(format!(
+                "{}-{}",
+                self.print_char(*start, CharContext::Class { first, last: false })?,
+                self.print_char(*end, CharContext::Class { first: false, last: false })?
+            )),
+            ClassItem::Escaped(esc) => self.print_escaped_char(esc, CharContext::Class { first, last }),
+            ClassItem::CharacterType(char_type) => self.print_character_type(char_type),
+            ClassItem::UnicodeCategory { negated, category } => {
+                self.print_unicode_category(category, *negated)
+            }
         }
     }
 
-    fn print_char(&self, c: char) -> String {
-        if self.use_unicode_escapes {
-            format!("\\u{{{:X}}}", c as u32)
+    fn print_character_type(&self, char_type: &CharacterTypeKind) -> PrintResult {
+        if let CharacterTypeKind::EscapedChar(esc) = char_type {
+            return self.print_escaped_char(esc, CharContext::TopLevel);
+        }
+        self.require(Feature::CharacterType)?;
+        let printed = match char_type {
+            CharacterTypeKind::Word => "\\w",
+            CharacterTypeKind::NotWord => "\\W",
+            CharacterTypeKind::Digit => "\\d",
+            CharacterTypeKind::NotDigit => "\\D",
+            CharacterTypeKind::Whitespace => "\\s",
+            CharacterTypeKind::NotWhitespace => "\\S",
+            CharacterTypeKind::EscapedChar(_) => unreachable!(),
+        };
+        Ok(printed.to_string())
+    }
+
+    fn print_unicode_category(&self, category: &UnicodeCategoryKind, negated: bool) -> PrintResult {
+        self.require(Feature::UnicodeCategory)?;
+        let name = match category {
+            UnicodeCategoryKind::Letter => 'L',
+            UnicodeCategoryKind::Number => 'N',
+            UnicodeCategoryKind::Punctuation => 'P',
+            UnicodeCategoryKind::Symbol => 'S',
+            UnicodeCategoryKind::Mark => 'M',
+            UnicodeCategoryKind::Separator => 'Z',
+            UnicodeCategoryKind::Other => 'C',
+        };
+        Ok(format!("\\{}{{{}}}", if negated { 'P' } else { 'p' }, name))
+    }
+
+    fn print_char(&self, c: char, context: CharContext) -> PrintResult {
+        self.check_code_point(c as u32, context)?;
+        if self.use_unicode_escapes && self.dialect != Dialect::PosixEre {
+            return Ok(self.print_code_point(c as u32, context));
+        }
+
+        let needs_escape = match (context, self.dialect) {
+            // Escaping anything else is undefined in POSIX
+            (CharContext::TopLevel, Dialect::PosixEre) => matches!(
+                c,
+                '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '[' | '{' | '|' | '^' | '$'
+            ),
+            (CharContext::TopLevel, _) => matches!(
+                c,
+                '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' | '^' | '$'
+            ),
+            // Bracket expressions are ordered instead, see print_posix_class_items
+            (CharContext::Class { .. }, Dialect::PosixEre) => false,
+            // `^` only negates at the start and `-` is literal at either end
+            (CharContext::Class { first, last }, _) => match c {
+                '\\' | ']' | '[' => true,
+                '^' => first,
+                '-' => !first && !last,
+                _ => false,
+            },
+        };
+
+        if needs_escape {
+            Ok(format!("\\{}", c))
         } else {
-            c.to_string()
+            Ok(c.to_string())
         }
     }
 
-    fn print_quantifier(&self, quantifier: &Quantifier) -> String {
-        match quantifier {
-            Quantifier::ZeroOrMore { lazy } => if *lazy { "*?" } else { "*" }.to_string(),
-            Quantifier::OneOrMore { lazy } => if *lazy { "+?" } else { "+" }.to_string(),
-            Quantifier::ZeroOrOne { lazy } => if *lazy { "??" } else { "?" }.to_string(),
+    /// The dialect's escape for any code point.
+    fn print_code_point(&self, n: u32, context: CharContext) -> String {
+        match self.dialect {
+            Dialect::Generic | Dialect::JavaScript | Dialect::PosixEre => format!("\\u{{{:X}}}", n),
+            Dialect::Pcre | Dialect::Re2 if n <= 0xFF => format!("\\x{:02X}", n),
+            Dialect::Pcre | Dialect::Re2 => format!("\\x{{{:X}}}", n),
+            Dialect::Python if n > 0xFFFF => format!("\\U{:08X}", n),
+            Dialect::Java if n > 0xFFFF => format!("\\x{{{:X}}}", n),
+            // A surrogate pair, grouped so a quantifier covers both halves
+            Dialect::DotNet if n > 0xFFFF => {
+                let n = n - 0x10000;
+                let pair = format!("\\u{:04X}\\u{:04X}", 0xD800 + (n >> 10), 0xDC00 + (n & 0x3FF));
+                match context {
+                    CharContext::TopLevel => format!("(?:{})", pair),
+                    CharContext::Class { .. } => pair,
+                }
+            }
+            Dialect::Python | Dialect::Java | Dialect::DotNet => format!("\\u{:04X}", n),
+        }
+    }
+
+    fn print_quantifier(&self, quantifier: &Quantifier) -> PrintResult {
+        let suffix = match quantifier.mode() {
+            QuantifierMode::Greedy => "",
+            QuantifierMode::Lazy => {
+                self.require(Feature::LazyQuantifier)?;
+                "?"
+            }
+            QuantifierMode::Possessive => {
+                self.require(Feature::PossessiveQuantifier)?;
+                "+"
+            }
+        };
+
+        let printed = match quantifier {
+            Quantifier::ZeroOrMore { .. } => "*".to_string(),
+            Quantifier::OneOrMore { .. } => "+".to_string(),
+            Quantifier::ZeroOrOne { .. } => "?".to_string(),
             Quantifier::Exactly(n) => format!("{{{}}}", n),
-            Quantifier::AtLeast(n) => format!("{{{},}}", n),
-            Quantifier::Range { min, max } => format!("{{{},{}}}", min, max),
+            Quantifier::AtLeast { min, .. } => format!("{{{},}}", min),
+            Quantifier::Range { min, max, .. } => format!("{{{},{}}}", min, max),
+        };
+        Ok(printed + suffix)
+    }
+
+    fn print_escaped_char(&self, escaped_char: &EscapedChar, context: CharContext) -> PrintResult {
+        if self.dialect == Dialect::PosixEre {
+            // POSIX has no escapes, so the char itself goes in the pattern
+            let c = escaped_char.to_char().ok_or_else(|| self.error(Feature::UnicodeEscape))?;
+            return self.print_char(c, context);
         }
+        if self.dialect == Dialect::Generic {
+            return Ok(self.print_generic_escape(escaped_char));
+        }
+
+        let printed = match escaped_char {
+            EscapedChar::Tab => "\\t".to_string(),
+            EscapedChar::NewLine => "\\n".to_string(),
+            EscapedChar::CarriageReturn => "\\r".to_string(),
+            EscapedChar::FormFeed => "\\f".to_string(),
+            // `\v` is any vertical whitespace in PCRE and Java
+            EscapedChar::VerticalTab if matches!(self.dialect, Dialect::Pcre | Dialect::Java) => {
+                "\\x0B".to_string()
+            }
+            EscapedChar::VerticalTab => "\\v".to_string(),
+            // `\0` runs into a following digit, and Java wants one anyway
+            EscapedChar::Null => "\\x00".to_string(),
+            EscapedChar::Octal(n) => match self.dialect {
+                Dialect::Pcre => format!("\\o{{{:o}}}", n),
+                Dialect::Java if *n <= 0o377 => format!("\\0{:03o}", n),
+                Dialect::Python | Dialect::Re2 | Dialect::DotNet if *n <= 0o77 => {
+                    format!("\\0{:02o}", n)
+                }
+                Dialect::Python | Dialect::Re2 if *n <= 0o377 => format!("\\{:03o}", n),
+                _ => self.print_hex(*n, context)?,
+            },
+            EscapedChar::Hex(n) => self.print_hex(*n, context)?,
+            EscapedChar::Unicode(n) => {
+                self.check_code_point(*n, context)?;
+                self.print_code_point(*n, context)
+            }
+        };
+        Ok(printed)
+    }
+
+    /// `\xHH` where it fits, the dialect's code point escape otherwise.
+    fn print_hex(&self, n: u32, context: CharContext) -> PrintResult {
+        if n <= 0xFF {
+            return Ok(format!("\\x{:02X}", n));
+        }
+        self.check_code_point(n, context)?;
+        Ok(self.print_code_point(n, context))
+    }
+
+    fn check_code_point(&self, n: u32, context: CharContext) -> Result<(), PrintError> {
+        // .NET matches UTF-16 code units, so this is two chars to a class
+        if self.dialect == Dialect::DotNet && n > 0xFFFF && context != CharContext::TopLevel {
+            return Err(self.error(Feature::AstralCharInClass));
+        }
+        Ok(())
     }
 
-    fn print_escaped_char(&self, escaped_char: &EscapedChar) -> String {
+    fn print_generic_escape(&self, escaped_char: &EscapedChar) -> String {
         match escaped_char {
             EscapedChar::Tab => "\\t".to_string(),
             EscapedChar::NewLine => "\\n".to_string(),
@@ -97,8 +465,26 @@ impl Printer {
             EscapedChar::FormFeed => "\\f".to_string(),
             EscapedChar::VerticalTab => "\\v".to_string(),
             EscapedChar::Null => "\\0".to_string(),
-            EscapedChar::Hex(n) => format!("\\x{:02X}", n),
-            EscapedChar::Unicode(n) => format!("\\u{{{:X}}}", n),
+            // Anything past `\xFF` and octal escapes only read back as `\u{...}`
+            EscapedChar::Hex(n) if *n <= 0xFF => format!("\\x{:02X}", n),
+            EscapedChar::Hex(n) | EscapedChar::Unicode(n) | EscapedChar::Octal(n) => {
+                format!("\\u{{{:X}}}", n)
+            }
         }
     }
-} 
\ No newline at end of file
+
+    fn require(&self, feature: Feature) -> Result<(), PrintError> {
+        if self.dialect.supports(feature) {
+            Ok(())
+        } else {
+            Err(self.error(feature))
+        }
+    }
+
+    fn error(&self, feature: Feature) -> PrintError {
+        PrintError {
+            feature,
+            dialect: self.dialect,
+        }
+    }
+}
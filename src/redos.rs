@@ -0,0 +1,354 @@
+//! Looks for the shapes that make a backtracking engine take exponential or
+//! polynomial time, and builds an input that triggers each one.
+//!
+//! Three shapes are recognised:
+//!
+//! - a loop nested in another loop where both can consume the same char,
+//!   such as `(a+)+` or `(\w+\s?)*`, which is exponential;
+//! - a loop over an alternation whose branches can match the same text, such
+//!   as `(\w|\d)+`, which is exponential too;
+//! - loops in a row that can consume the same char, such as `\d+\d+`, which
+//!   is polynomial with one degree per loop.
+//!
+//! Every finding is checked by running the attack on the [`Matcher`]: if the
+//! pattern still matches the attack string it backtracks at most once, and
+//! the finding is dropped.
+
+use std::fmt;
+
+use crate::ast::{AnchorType, GroupKind, Quantifier, QuantifierMode, RegexFlags, RegexNode};
+use crate::generate::{self, Generator};
+use crate::matcher::{self, Matcher};
+
+// Chars tried at the end of an attack, to make the overall match fail
+const SUFFIXES: &[char] = &['!', '\n', '\0', ' ', 'a', '0', '_', '-'];
+
+// Repetitions of the pump used to check that an attack fails to match
+const CHECK_REPEAT: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Complexity {
+    Linear,
+    /// Backtracking grows with the input length to this power.
+    Polynomial(u32),
+    Exponential,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FindingKind {
+    NestedQuantifier,
+    AmbiguousAlternation,
+    OverlappingQuantifiers,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub complexity: Complexity,
+    pub attack: Attack,
+}
+
+/// An input of the form `prefix pump pump ... pump suffix`. The more times
+/// the pump repeats, the longer the matcher backtracks before failing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attack {
+    pub prefix: String,
+    pub pump: String,
+    pub suffix: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// The worst complexity among the findings, or linear if there are none
+    pub complexity: Complexity,
+    pub findings: Vec<Finding>,
+}
+
+impl fmt::Display for Complexity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Complexity::Linear => write!(f, "linear"),
+            Complexity::Polynomial(degree) => write!(f, "polynomial (degree {})", degree),
+            Complexity::Exponential => write!(f, "exponential"),
+        }
+    }
+}
+
+impl Attack {
+    pub fn build(&self, repeat: usize) -> String {
+        format!("{}{}{}", self.prefix, self.pump.repeat(repeat), self.suffix)
+    }
+}
+
+impl Report {
+    /// The attack of the worst finding, with enough repetitions to take a
+    /// backtracking engine seconds or more.
+    pub fn attack(&self) -> Option<String> {
+        let worst = self.findings.iter().max_by_key(|finding| finding.complexity)?;
+        let repeat = match worst.complexity {
+            Complexity::Exponential => 32,
+            _ => 10_000,
+        };
+        Some(worst.attack.build(repeat))
+    }
+}
+
+pub fn analyze(nodes: &[RegexNode]) -> Report {
+    let mut analyzer = Analyzer {
+        root: nodes,
+        chars: generate::alphabet(nodes),
+        generator: Generator::with_seed(0).with_max_repeat(1),
+        findings: Vec::new(),
+    };
+    analyzer.sequence(nodes, &RegexFlags::new(), String::new());
+
+    let findings = analyzer.findings;
+    Report {
+        complexity: findings
+            .iter()
+            .map(|finding| finding.complexity)
+            .max()
+            .unwrap_or(Complexity::Linear),
+        findings,
+    }
+}
+
+struct Analyzer<'a> {
+    root: &'a [RegexNode],
+    // Chars tried as pumps
+    chars: Vec<char>,
+    // Builds the text that leads up to a loop
+    generator: Generator,
+    findings: Vec<Finding>,
+}
+
+impl Analyzer<'_> {
+    /// Checks a sequence and everything nested in it. `prefix` is text that
+    /// gets the matcher to the start of the sequence.
+    fn sequence(&mut self, nodes: &[RegexNode], flags: &RegexFlags, mut prefix: String) {
+        self.overlapping_loops(nodes, flags, &prefix);
+        for node in nodes {
+            self.node(node, flags, &prefix);
+            prefix.push_str(&self.generator.sample(std::slice::from_ref(node)));
+        }
+    }
+
+    fn node(&mut self, node: &RegexNode, flags: &RegexFlags, prefix: &str) {
+        match node {
+            RegexNode::Quantified { node: body, quantifier } => {
+                if backtracks(quantifier) {
+                    self.nested_loops(body, flags, prefix);
+                    self.ambiguous_alternation(body, flags, prefix);
+                }
+                self.node(body, flags, prefix);
+            }
+            RegexNode::Group(_, nodes) | RegexNode::Lookaround(_, nodes) => {
+                self.sequence(nodes, flags, prefix.to_string())
+            }
+            RegexNode::FlagSet(new_flags, _, nodes) => {
+                self.sequence(nodes, &flags.apply(new_flags), prefix.to_string())
+            }
+            RegexNode::Alternation(alternatives) => {
+                for alt in alternatives {
+                    self.sequence(alt, flags, prefix.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `(a+)+`: an inner loop that can make up the whole of an outer
+    /// iteration, so each run of the pump can be split between the two in
+    /// exponentially many ways.
+    fn nested_loops(&mut self, body: &RegexNode, flags: &RegexFlags, prefix: &str) {
+        let mut inner = Vec::new();
+        lone_loops(std::slice::from_ref(body), &mut inner);
+        for inner in inner {
+            if let Some(pump) = self.single_char(&[inner], flags) {
+                self.report(FindingKind::NestedQuantifier, Complexity::Exponential, prefix, pump);
+                return;
+            }
+        }
+    }
+
+    /// `(a|a)+`: two branches of a repeated alternation that match the same
+    /// text, so every iteration can go either way.
+    fn ambiguous_alternation(&mut self, body: &RegexNode, flags: &RegexFlags, prefix: &str) {
+        let Some(alternatives) = alternation_of(body) else {
+            return;
+        };
+        for (i, first) in alternatives.iter().enumerate() {
+            for second in &alternatives[i + 1..] {
+                let mut candidates: Vec<String> = self.chars.iter().map(|c| c.to_string()).collect();
+                candidates.push(self.generator.sample(first));
+                candidates.push(self.generator.sample(second));
+                let pump = candidates.into_iter().find(|text| {
+                    !text.is_empty()
+                        && matches_fully(first, flags, text)
+                        && matches_fully(second, flags, text)
+                });
+                if let Some(pump) = pump {
+                    self.report(FindingKind::AmbiguousAlternation, Complexity::Exponential, prefix, pump);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// `\d+\d+`: loops in a row, with nothing but optional nodes between them,
+    /// that can all consume the same char. A run of it can be split between
+    /// them in polynomially many ways.
+    fn overlapping_loops(&mut self, nodes: &[RegexNode], flags: &RegexFlags, prefix: &str) {
+        let mut start = 0;
+        while start < nodes.len() {
+            let mut end = start + 1;
+            let mut degree = 0;
+            let mut pump = None;
+
+            if let RegexNode::Quantified { node: first, quantifier } = &nodes[start] {
+                if backtracks(quantifier) {
+                    for &c in &self.chars {
+                        let mut count = 1;
+                        let mut last = start;
+                        for (j, node) in nodes.iter().enumerate().skip(start + 1) {
+                            match node {
+                                RegexNode::Quantified { node: next, quantifier }
+                                    if backtracks(quantifier)
+                                        && matches_fully(std::slice::from_ref(next), flags, &c.to_string()) =>
+                                {
+                                    count += 1;
+                                    last = j;
+                                }
+                                node if nullable(node) => {}
+                                _ => break,
+                            }
+                        }
+                        let matches_first = matches_fully(std::slice::from_ref(first), flags, &c.to_string());
+                        if matches_first && count > degree {
+                            degree = count;
+                            end = last + 1;
+                            pump = Some(c);
+                        }
+                    }
+                }
+            }
+
+            if let (true, Some(pump)) = (degree >= 2, pump) {
+                let prefix = format!("{}{}", prefix, self.generator.sample(&nodes[..start]));
+                let complexity = Complexity::Polynomial(degree as u32);
+                self.report(FindingKind::OverlappingQuantifiers, complexity, &prefix, pump.to_string());
+                start = end;
+            } else {
+                start += 1;
+            }
+        }
+    }
+
+    /// A char that the whole sequence matches on its own.
+    fn single_char(&self, nodes: &[&RegexNode], flags: &RegexFlags) -> Option<String> {
+        let nodes: Vec<RegexNode> = nodes.iter().map(|&node| node.clone()).collect();
+        self.chars
+            .iter()
+            .map(|c| c.to_string())
+            .find(|text| matches_fully(&nodes, flags, text))
+    }
+
+    /// Records a finding if some suffix makes the whole pattern fail on the
+    /// attack string.
+    fn report(&mut self, kind: FindingKind, complexity: Complexity, prefix: &str, pump: impl Into<String>) {
+        let pump = pump.into();
+        let matcher = Matcher::new(self.root).with_step_limit(100_000);
+        let attack = SUFFIXES.iter().find_map(|suffix| {
+            let attack = Attack {
+                prefix: prefix.to_string(),
+                pump: pump.clone(),
+                suffix: suffix.to_string(),
+            };
+            // Running out of steps is as good a failure as any
+            let fails = !matches!(matcher.is_match(&attack.build(CHECK_REPEAT)), Ok(true));
+            fails.then_some(attack)
+        });
+        if let Some(attack) = attack {
+            self.findings.push(Finding { kind, complexity, attack });
+        }
+    }
+}
+
+/// Whether a loop can be made to give back or take more iterations without
+/// limit. Possessive loops never give any back.
+fn backtracks(quantifier: &Quantifier) -> bool {
+    let (_, max, mode) = matcher::bounds(quantifier);
+    max.is_none() && mode != QuantifierMode::Possessive
+}
+
+/// The bodies of unbounded loops that can make up the whole of `nodes`, with
+/// everything around them able to match empty.
+fn lone_loops<'a>(nodes: &'a [RegexNode], loops: &mut Vec<&'a RegexNode>) {
+    for (i, node) in nodes.iter().enumerate() {
+        let others_nullable = nodes
+            .iter()
+            .enumerate()
+            .all(|(j, other)| j == i || nullable(other));
+        if !others_nullable {
+            continue;
+        }
+        match node {
+            RegexNode::Quantified { node: body, quantifier } if backtracks(quantifier) => loops.push(body),
+            // Nothing outside can make an atomic body match another way
+            RegexNode::Quantified { quantifier, .. } if quantifier.mode() == QuantifierMode::Possessive => {}
+            RegexNode::Group(GroupKind::Atomic, _) => {}
+            RegexNode::Quantified { node: body, .. } => lone_loops(std::slice::from_ref(body), loops),
+            RegexNode::Group(_, nodes) | RegexNode::FlagSet(_, _, nodes) => lone_loops(nodes, loops),
+            RegexNode::Alternation(alternatives) => {
+                for alt in alternatives {
+                    lone_loops(alt, loops);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The branches of an alternation that makes up the whole node, looking
+/// through groups.
+fn alternation_of(node: &RegexNode) -> Option<&[Vec<RegexNode>]> {
+    match node {
+        RegexNode::Alternation(alternatives) => Some(alternatives),
+        RegexNode::Group(GroupKind::Atomic, _) => None,
+        RegexNode::Group(_, nodes) | RegexNode::FlagSet(_, _, nodes) if nodes.len() == 1 => {
+            alternation_of(&nodes[0])
+        }
+        _ => None,
+    }
+}
+
+/// Whether a node can match without consuming anything.
+fn nullable(node: &RegexNode) -> bool {
+    match node {
+        RegexNode::Anchor(_)
+        | RegexNode::WordBoundary
+        | RegexNode::Lookaround(..)
+        | RegexNode::Backreference(_) => true,
+        RegexNode::Quantified { node, quantifier } => {
+            matcher::bounds(quantifier).0 == 0 || nullable(node)
+        }
+        RegexNode::Group(_, nodes) | RegexNode::FlagSet(_, _, nodes) => nodes.iter().all(nullable),
+        RegexNode::Alternation(alternatives) => {
+            alternatives.iter().any(|alt| alt.iter().all(nullable))
+        }
+        _ => false,
+    }
+}
+
+/// Whether `nodes` match all of `text`, under `flags`.
+fn matches_fully(nodes: &[RegexNode], flags: &RegexFlags, text: &str) -> bool {
+    let anchored = [
+        RegexNode::new_anchor(AnchorType::Start),
+        RegexNode::new_scoped_flag_set(
+            flags.clone(),
+            vec![RegexNode::new_group(GroupKind::NonCapturing, nodes.to_vec())],
+        ),
+        RegexNode::new_anchor(AnchorType::End),
+    ];
+    matches!(Matcher::new(&anchored).with_step_limit(10_000).is_match(text), Ok(true))
+}
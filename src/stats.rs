@@ -0,0 +1,109 @@
+//! Size and complexity measures of a pattern, used to compare obfuscated
+//! output against its input.
+
+use crate::ast::{self, GroupKind, RegexNode};
+use crate::matcher;
+use crate::printer::{PrintError, Printer};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub node_count: usize,
+    /// How deeply nodes nest, a flat sequence being 1 deep
+    pub max_depth: usize,
+    pub capture_groups: usize,
+    /// Length of the printed pattern in chars
+    pub printed_length: usize,
+    /// Branches of every alternation added up
+    pub alternation_branches: usize,
+    /// States a Thompson NFA for the pattern would have, counting a
+    /// backreference or lookaround as one
+    pub nfa_states: usize,
+}
+
+impl Stats {
+    /// Measures `nodes`, failing if the printer's dialect can't print them.
+    pub fn of(nodes: &[RegexNode], printer: &Printer) -> Result<Stats, PrintError> {
+        let mut node_count = 0;
+        let mut capture_groups = 0;
+        let mut alternation_branches = 0;
+        ast::walk(nodes, &mut |node| {
+            node_count += 1;
+            match node {
+                RegexNode::Group(GroupKind::Capturing(_), _) => capture_groups += 1,
+                RegexNode::Alternation(alternatives) => alternation_branches += alternatives.len(),
+                _ => {}
+            }
+        });
+
+        Ok(Stats {
+            node_count,
+            max_depth: depth(nodes),
+            capture_groups,
+            printed_length: printer.try_print(nodes)?.chars().count(),
+            alternation_branches,
+            // One more for the match state
+            nfa_states: sequence_states(nodes).saturating_add(1),
+        })
+    }
+
+    /// How many times longer the printed pattern of `after` is.
+    pub fn growth(&self, after: &Stats) -> f64 {
+        after.printed_length as f64 / self.printed_length.max(1) as f64
+    }
+}
+
+fn depth(nodes: &[RegexNode]) -> usize {
+    nodes
+        .iter()
+        .map(|node| {
+            1 + match node {
+                RegexNode::Quantified { node, .. } => depth(std::slice::from_ref(node.as_ref())),
+                RegexNode::Group(_, nodes)
+                | RegexNode::Lookaround(_, nodes)
+                | RegexNode::FlagSet(_, _, nodes) => depth(nodes),
+                RegexNode::Alternation(alternatives) => {
+                    alternatives.iter().map(|alt| depth(alt)).max().unwrap_or(0)
+                }
+                _ => 0,
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn sequence_states(nodes: &[RegexNode]) -> usize {
+    nodes.iter().map(node_states).fold(0, usize::saturating_add)
+}
+
+/// Follows how [`Nfa::compile`](crate::nfa::Nfa::compile) lays out states.
+fn node_states(node: &RegexNode) -> usize {
+    match node {
+        RegexNode::Quantified { node, quantifier } => {
+            let (min, max, _) = matcher::bounds(quantifier);
+            let body = node_states(node);
+            // Nested counted repeats can outgrow usize, which saturates
+            match max {
+                // The body repeated, plus a split for each optional copy
+                Some(max) => min
+                    .saturating_mul(body)
+                    .saturating_add((max - min).saturating_mul(body.saturating_add(1))),
+                None => min.saturating_add(1).saturating_mul(body).saturating_add(1),
+            }
+        }
+        // Two saves around the body
+        RegexNode::Group(GroupKind::Capturing(_), nodes) => sequence_states(nodes).saturating_add(2),
+        RegexNode::Group(GroupKind::NonCapturing | GroupKind::Atomic, nodes)
+        | RegexNode::FlagSet(_, _, nodes) => {
+            sequence_states(nodes)
+        }
+        // A split for every branch but the last
+        RegexNode::Alternation(alternatives) => {
+            let splits = alternatives.len().saturating_sub(1);
+            alternatives
+                .iter()
+                .map(|alt| sequence_states(alt))
+                .fold(splits, usize::saturating_add)
+        }
+        _ => 1,
+    }
+}
@@ -1,10 +1,23 @@
 #[cfg(test)]
 mod tests {
     use crate::ast::{
-        AnchorType, BackreferenceKind, CharacterTypeKind, EscapedChar, GroupKind, LookaroundKind,
-        Quantifier, RegexFlags, RegexNode, UnicodeCategoryKind,
+        self, AnchorType, BackreferenceKind, CharacterTypeKind, ClassItem, EscapedChar, FlagChange,
+        GroupKind, LookaroundKind, Quantifier, QuantifierMode, RegexFlags, RegexNode,
+        UnicodeCategoryKind,
     };
-    use crate::parser::Parser;
+    use crate::matcher::{Match, MatchError, Matcher};
+    use crate::dfa::{self, Equivalence};
+    use crate::dialect::{Dialect, Feature};
+    use crate::nfa::{CompileError, Nfa};
+    use crate::obfuscator::{Intensity, ObfuscationBudget, Obfuscator, Pass, PassContext};
+    use crate::passes::LiteralToClass;
+    use crate::parser::{ParseErrorKind, Parser, Span};
+    use crate::printer::{PrintError, Printer};
+    use crate::redos::{self, Complexity, FindingKind};
+    use crate::stats::Stats;
+    use crate::translate::{self, Untranslatable};
+    use crate::generate::Generator;
+    use crate::verify::Verifier;
 
     #[test]
     fn test_basic_parsing() {
@@ -30,6 +43,97 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_character_class_ranges() {
+        let mut parser = Parser::new("[a-z0-9_]");
+        let result = parser.parse().unwrap();
+        assert_eq!(
+            result,
+            vec![RegexNode::new_class(
+                vec![
+                    ClassItem::new_range('a', 'z'),
+                    ClassItem::new_range('0', '9'),
+                    ClassItem::Char('_'),
+                ],
+                false
+            )]
+        );
+    }
+
+    #[test]
+    fn test_character_class_literal_dashes() {
+        let mut parser = Parser::new("[-a-]");
+        let result = parser.parse().unwrap();
+        assert_eq!(
+            result,
+            vec![RegexNode::new_char_class(vec!['-', 'a', '-'], false)]
+        );
+    }
+
+    #[test]
+    fn test_character_class_escapes() {
+        let mut parser = Parser::new("[^\\d\\n\\]\\x00-\\x1F\\p{L}]");
+        let result = parser.parse().unwrap();
+        assert_eq!(
+            result,
+            vec![RegexNode::new_class(
+                vec![
+                    ClassItem::CharacterType(CharacterTypeKind::Digit),
+                    ClassItem::Escaped(EscapedChar::NewLine),
+                    ClassItem::Char(']'),
+                    ClassItem::new_range('\0', '\u{1F}'),
+                    ClassItem::UnicodeCategory {
+                        negated: false,
+                        category: UnicodeCategoryKind::Letter,
+                    },
+                ],
+                true
+            )]
+        );
+    }
+
+    #[test]
+    fn test_invalid_class_ranges() {
+        for pattern in ["[z-a]", "[a-\\d]"] {
+            let mut parser = Parser::new(pattern);
+            assert!(parser.parse().is_err(), "{} should not parse", pattern);
+        }
+    }
+
+    #[test]
+    fn test_character_class_round_trip() {
+        let mut parser = Parser::new("[a-z0-9_]");
+        let ast = parser.parse().unwrap();
+        assert_eq!(Printer::new(false).print(&ast), "[a-z0-9_]");
+    }
+
+    #[test]
+    fn test_obfuscated_class_ranges_cover_original() {
+        let mut parser = Parser::new("[a-z]");
+        let ast = parser.parse().unwrap();
+        let obfuscated = Obfuscator::new().obfuscate(ast);
+
+        let alternatives = match &obfuscated[..] {
+            [RegexNode::Group(GroupKind::NonCapturing, nodes)] => match &nodes[..] {
+                [RegexNode::Alternation(alternatives)] => alternatives.clone(),
+                other => panic!("expected alternation, got {:?}", other),
+            },
+            other => panic!("expected non-capturing group, got {:?}", other),
+        };
+
+        let mut covered = Vec::new();
+        for alternative in alternatives {
+            match &alternative[..] {
+                [RegexNode::CharacterClass { negated: false, items }] => match &items[..] {
+                    [ClassItem::Range { start, end }] => covered.extend(*start..=*end),
+                    other => panic!("expected a single range, got {:?}", other),
+                },
+                other => panic!("expected a single-item class, got {:?}", other),
+            }
+        }
+        assert_eq!(covered, ('a'..='z').collect::<Vec<_>>());
+    }
+
     #[test]
     fn test_anchors() {
         let mut parser = Parser::new("^abc$");
@@ -51,15 +155,15 @@ mod tests {
         let test_cases = vec![
             (
                 "a*",
-                vec![RegexNode::new_literal('a').with_quantifier(Quantifier::ZeroOrMore { lazy: false })]
+                vec![RegexNode::new_literal('a').with_quantifier(Quantifier::ZeroOrMore { mode: QuantifierMode::Greedy })]
             ),
             (
                 "b+",
-                vec![RegexNode::new_literal('b').with_quantifier(Quantifier::OneOrMore { lazy: false })]
+                vec![RegexNode::new_literal('b').with_quantifier(Quantifier::OneOrMore { mode: QuantifierMode::Greedy })]
             ),
             (
                 "c?",
-                vec![RegexNode::new_literal('c').with_quantifier(Quantifier::ZeroOrOne { lazy: false })]
+                vec![RegexNode::new_literal('c').with_quantifier(Quantifier::ZeroOrOne { mode: QuantifierMode::Greedy })]
             ),
         ];
 
@@ -75,15 +179,15 @@ mod tests {
         let test_cases = vec![
             (
                 "a*?",
-                vec![RegexNode::new_literal('a').with_quantifier(Quantifier::ZeroOrMore { lazy: true })]
+                vec![RegexNode::new_literal('a').with_quantifier(Quantifier::ZeroOrMore { mode: QuantifierMode::Lazy })]
             ),
             (
                 "b+?",
-                vec![RegexNode::new_literal('b').with_quantifier(Quantifier::OneOrMore { lazy: true })]
+                vec![RegexNode::new_literal('b').with_quantifier(Quantifier::OneOrMore { mode: QuantifierMode::Lazy })]
             ),
             (
                 "c??",
-                vec![RegexNode::new_literal('c').with_quantifier(Quantifier::ZeroOrOne { lazy: true })]
+                vec![RegexNode::new_literal('c').with_quantifier(Quantifier::ZeroOrOne { mode: QuantifierMode::Lazy })]
             ),
         ];
 
@@ -94,6 +198,59 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_atomic_groups_and_possessive_quantifiers() {
+        let possessive = |quantifier: Quantifier| {
+            RegexNode::new_literal('a').with_quantifier(quantifier.with_mode(QuantifierMode::Possessive))
+        };
+        let test_cases = vec![
+            ("a*+", vec![possessive(Quantifier::ZeroOrMore { mode: QuantifierMode::Greedy })]),
+            ("a?+", vec![possessive(Quantifier::ZeroOrOne { mode: QuantifierMode::Greedy })]),
+            ("a{1,3}+", vec![possessive(Quantifier::Range { min: 1, max: 3, mode: QuantifierMode::Greedy })]),
+            (
+                "a{2}+",
+                vec![RegexNode::new_group(GroupKind::Atomic, vec![possessive(Quantifier::Exactly(2))])],
+            ),
+            (
+                "(?>a|b)",
+                vec![RegexNode::new_group(
+                    GroupKind::Atomic,
+                    vec![RegexNode::new_alternation(vec![
+                        vec![RegexNode::new_literal('a')],
+                        vec![RegexNode::new_literal('b')],
+                    ])],
+                )],
+            ),
+        ];
+        for (pattern, expected) in test_cases {
+            assert_eq!(Parser::new(pattern).parse().unwrap(), expected, "{}", pattern);
+        }
+
+        let printer = Printer::new(false);
+        for pattern in ["a++b*+c?+", "x{2,}+y{1,3}+", "(?>ab|a)c"] {
+            assert_eq!(printer.print(&Parser::new(pattern).parse().unwrap()), pattern);
+        }
+
+        // Nothing after them can make them give back what they matched
+        let is_match = |pattern: &str, haystack: &str| {
+            Matcher::new(&Parser::new(pattern).parse().unwrap()).is_match(haystack).unwrap()
+        };
+        assert!(is_match("a+a", "aaa"));
+        assert!(!is_match("a++a", "aaa"));
+        assert!(is_match("(?:ab|a)bc", "abc"));
+        assert!(!is_match("(?>ab|a)bc", "abc"));
+        assert!(is_match("(?>ab|a)bc", "abbc"));
+
+        for (pattern, feature, dialect) in [
+            ("(?>a)", Feature::AtomicGroup, Dialect::JavaScript),
+            ("a++", Feature::PossessiveQuantifier, Dialect::DotNet),
+            ("a{2}+", Feature::PossessiveQuantifier, Dialect::Re2),
+        ] {
+            let err = Parser::new(pattern).with_dialect(dialect).parse().unwrap_err();
+            assert_eq!(err.kind, ParseErrorKind::Unsupported(feature, dialect), "{}", pattern);
+        }
+    }
+
     #[test]
     fn test_curly_quantifiers() {
         let test_cases = vec![
@@ -103,11 +260,19 @@ mod tests {
             ),
             (
                 "b{2,}",
-                vec![RegexNode::new_literal('b').with_quantifier(Quantifier::AtLeast(2))]
+                vec![RegexNode::new_literal('b').with_quantifier(Quantifier::AtLeast { min: 2, mode: QuantifierMode::Greedy })]
             ),
             (
                 "c{1,3}",
-                vec![RegexNode::new_literal('c').with_quantifier(Quantifier::Range { min: 1, max: 3 })]
+                vec![RegexNode::new_literal('c').with_quantifier(Quantifier::Range { min: 1, max: 3, mode: QuantifierMode::Greedy })]
+            ),
+            (
+                "b{2,}?",
+                vec![RegexNode::new_literal('b').with_quantifier(Quantifier::AtLeast { min: 2, mode: QuantifierMode::Lazy })]
+            ),
+            (
+                "c{1,3}?",
+                vec![RegexNode::new_literal('c').with_quantifier(Quantifier::Range { min: 1, max: 3, mode: QuantifierMode::Lazy })]
             ),
         ];
 
@@ -239,7 +404,7 @@ mod tests {
                     RegexNode::new_literal('b'),
                     RegexNode::new_literal('c'),
                 ]
-            ).with_quantifier(Quantifier::OneOrMore { lazy: false })]
+            ).with_quantifier(Quantifier::OneOrMore { mode: QuantifierMode::Greedy })]
         );
     }
 
@@ -322,9 +487,9 @@ mod tests {
             result,
             vec![RegexNode::new_alternation(vec![
                 vec![RegexNode::new_character_type(CharacterTypeKind::Word)
-                    .with_quantifier(Quantifier::OneOrMore { lazy: false })],
+                    .with_quantifier(Quantifier::OneOrMore { mode: QuantifierMode::Greedy })],
                 vec![RegexNode::new_character_type(CharacterTypeKind::Digit)
-                    .with_quantifier(Quantifier::OneOrMore { lazy: false })],
+                    .with_quantifier(Quantifier::OneOrMore { mode: QuantifierMode::Greedy })],
             ])]
         );
     }
@@ -583,7 +748,7 @@ mod tests {
             result,
             vec![
                 RegexNode::new_character_type(CharacterTypeKind::Word)
-                    .with_quantifier(Quantifier::OneOrMore { lazy: false }),
+                    .with_quantifier(Quantifier::OneOrMore { mode: QuantifierMode::Greedy }),
                 RegexNode::new_lookaround(
                     LookaroundKind::PositiveLookahead,
                     vec![RegexNode::new_character_type(CharacterTypeKind::Digit)],
@@ -640,7 +805,7 @@ mod tests {
         assert_eq!(
             result,
             vec![
-                RegexNode::new_flag_set(
+                RegexNode::new_scoped_flag_set(
                     flags,
                     vec![
                         RegexNode::new_literal('f'),
@@ -704,7 +869,7 @@ mod tests {
         assert_eq!(
             result,
             vec![
-                RegexNode::new_flag_set(
+                RegexNode::new_scoped_flag_set(
                     flags,
                     vec![RegexNode::new_alternation(vec![
                         vec![
@@ -725,4 +890,1362 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_flag_negation() {
+        let mut set = RegexFlags::new();
+        set.case_insensitive = true;
+        let mut clear = RegexFlags::new();
+        clear.dot_all = true;
+        assert_eq!(
+            Parser::new("(?i-s:a)").parse().unwrap(),
+            vec![RegexNode::new_scoped_flag_set(FlagChange { set, clear }, vec![RegexNode::new_literal('a')])]
+        );
+
+        let is_match = |pattern: &str, haystack: &str| {
+            Matcher::new(&Parser::new(pattern).parse().unwrap()).is_match(haystack).unwrap()
+        };
+        assert!(is_match("(?i)a(?-i:b)c", "AbC"));
+        assert!(!is_match("(?i)a(?-i:b)c", "ABC"));
+        assert!(!is_match("(?s)a(?-s).", "a\n"));
+        // Flags set inside a group end with it
+        assert!(is_match("((?i)a)b", "Ab"));
+        assert!(!is_match("((?i)a)b", "AB"));
+        assert!(!is_match("(?i)(?:(?-i)a)b", "AB"));
+
+        let err = Parser::new("(?-i)a").with_dialect(Dialect::Python).parse().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Unsupported(Feature::UnscopedFlags, Dialect::Python));
+        for pattern in ["(?--i)a", "(?i-q)a"] {
+            assert_eq!(Parser::new(pattern).parse().unwrap_err().kind, ParseErrorKind::InvalidFlag, "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_unscoped_flags_end_at_bar() {
+        let mut flags = RegexFlags::new();
+        flags.case_insensitive = true;
+        // `a(?i)b|c` is `ab|c`, with `c` under the flags too
+        assert_eq!(
+            Parser::new("a(?i)b|c").parse().unwrap(),
+            vec![RegexNode::new_alternation(vec![
+                vec![
+                    RegexNode::new_literal('a'),
+                    RegexNode::new_flag_set(flags.clone(), vec![RegexNode::new_literal('b')]),
+                ],
+                vec![RegexNode::new_flag_set(flags, vec![RegexNode::new_literal('c')])],
+            ])]
+        );
+
+        let is_match = |pattern: &str, haystack: &str| {
+            Matcher::new(&Parser::new(pattern).parse().unwrap()).is_match(haystack).unwrap()
+        };
+        assert!(is_match("^(?:a(?i)b|c)$", "c"));
+        assert!(is_match("^(?:a(?i)b|c)$", "C"));
+        assert!(is_match("^(?:a(?i)b|c)$", "aB"));
+        assert!(!is_match("^(?:a(?i)b|c)$", "Ab"));
+        assert!(!is_match("^(?:a(?i)b|c)$", "ac"));
+        // The flags carried past a `|` still end with the group
+        assert!(!is_match("^(?:(?:a(?i)b|c)d)$", "cD"));
+        assert!(is_match("^(?:a(?i)b|c(?-i)d|e)$", "Cd"));
+        assert!(!is_match("^(?:a(?i)b|c(?-i)d|e)$", "CD"));
+        assert!(!is_match("^(?:a(?i)b|c(?-i)d|e)$", "E"));
+
+        let printer = Printer::new(false);
+        for pattern in ["a(?i)b|c", "a(?i)b|(?m)c|d", "(?i)a|(?-i)b", "(?:a(?i)b|c(?s)d|e)f"] {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let printed = printer.print(&ast);
+            assert_eq!(Parser::new(&printed).parse().unwrap(), ast, "{} printed as {}", pattern, printed);
+        }
+
+        let ast = Parser::new("a(?i)b|c").parse().unwrap();
+        let test_cases = [
+            (Dialect::Pcre, "a(?i)b|(?i)c"),
+            (Dialect::JavaScript, "a(?i:b)|(?i:c)"),
+            (Dialect::Python, "a(?i:b)|(?i:c)"),
+            (Dialect::PosixEre, "a[bB]|[cC]"),
+        ];
+        let mut verifier = Verifier::with_seed(7);
+        for (dialect, expected) in test_cases {
+            let translated = translate::translate(&ast, dialect).unwrap();
+            assert_eq!(Printer::new(false).with_dialect(dialect).print(&translated), expected, "{:?}", dialect);
+            assert!(verifier.verify(&ast, &translated).is_ok(), "{:?}", dialect);
+        }
+    }
+
+    #[test]
+    fn test_flags_read_while_parsing() {
+        // `x`, `U` and `n` are used up by the parser, so only their group is printed
+        let printer = Printer::new(false);
+        let test_cases = [
+            ("(?x) a b+ # a comment\n c", Dialect::Generic, "(?:ab+c)"),
+            // Only inside the group
+            ("(?x:a\\ \\# [ #]) #", Dialect::Generic, "(?:a #[ #]) #"),
+            ("(?x)a {2}(?-x: )", Dialect::Generic, "(?:a{2}(?: ))"),
+            ("(?U)a+b*?c??", Dialect::Pcre, "(?:a+?b*c?)"),
+            ("(?n)(a)(?<x>b)\\1", Dialect::DotNet, "(?:(?:a)(?<x>b)\\1)"),
+            ("(?iu)a", Dialect::Java, "(?iu)a"),
+        ];
+        for (pattern, dialect, expected) in test_cases {
+            let ast = Parser::new(pattern).with_dialect(dialect).parse().unwrap();
+            assert_eq!(printer.print(&ast), expected, "{}", pattern);
+        }
+
+        // Java's `U` means something else, and JavaScript has only `i`, `m` and `s`
+        for (pattern, dialect) in [("(?U)a", Dialect::Java), ("(?x)a", Dialect::JavaScript)] {
+            let err = Parser::new(pattern).with_dialect(dialect).parse().unwrap_err();
+            assert_eq!(err.kind, ParseErrorKind::InvalidFlag, "{:?}", dialect);
+        }
+        let ast = Parser::new("(?u)a").parse().unwrap();
+        assert_eq!(Printer::new(false).with_dialect(Dialect::Pcre).print(&ast), "(?:a)");
+    }
+
+    // Every pattern the parser tests above accept, used for printer round trips.
+    const PARSER_CORPUS: &[&str] = &[
+        "abc", "[abc]", "[a-z]", "[a-z0-9_]", "[-a-]", "^abc$",
+        "a*", "b+", "c?", "a*?", "b+?", "c??", "a{3}", "b{2,}", "c{1,3}", "b{2,}?", "c{1,3}?",
+        "(abc)", "(?:abc)", "(?<test>abc)", "(a)\\1", "(?<test>a)\\k<test>",
+        "(a(?:b(c)))", "(abc)+", "cat|dog", "foo|bar|baz", "(cat|dog)",
+        "\\w+|\\d+", "a(b|c)d",
+        "\\w", "\\W", "\\d", "\\D", "\\s", "\\S",
+        "\\n", "\\t", "\\r", "\\x20", "\\u{1F600}",
+        "\\p{L}", "\\P{N}", "\\p{P}",
+        "(?=foo)bar", "(?!foo)bar", "(?<=foo)bar", "(?<!foo)bar",
+        "foo(?!bar|baz)", "\\w+(?=\\d)",
+        "(?i)abc", "(?im)abc", "(?i:foo)bar", "(?m)^abc$", "(?s)a.c", "(?i:foo|bar)baz",
+        "(?i-s:a.)b", "(?i)a(?-i:b)c",
+    ];
+
+    #[test]
+    fn test_dialect_parsing() {
+        let printer = Printer::new(false);
+        let test_cases = vec![
+            (Dialect::Python, "(?P<word>\\w+)(?P=word)", "(?<word>\\w+)\\k<word>"),
+            (Dialect::Python, "\\u00e9\\U0001F600\\101", "\\u{E9}\\u{1F600}\\u{41}"),
+            (Dialect::Python, "(?i)(?s)a.", "(?i)(?s)a."),
+            (Dialect::JavaScript, "(?<y>\\u0041)\\k<y>(?i:\\u{1F600})", "(?<y>\\u{41})\\k<y>(?i:\\u{1F600})"),
+            (Dialect::Pcre, "(?'a'x)\\k{a}\\g1\\g{a}", "(?<a>x)\\k<a>\\1\\k<a>"),
+            (Dialect::Pcre, "\\x{263A}\\o{101}\\012\\pL", "\\u{263A}\\u{41}\\u{A}\\p{L}"),
+            (Dialect::Re2, "(?P<n>\\x{41})\\101\\PN", "(?<n>\\x41)\\u{41}\\P{N}"),
+            (Dialect::DotNet, "(?'a'x)\\k'a'(?<=a+)", "(?<a>x)\\k<a>(?<=a+)"),
+            (Dialect::Java, "\\0101\\x{41}(?<=ab?)", "\\u{41}\\x41(?<=ab?)"),
+        ];
+        for (dialect, pattern, expected) in test_cases {
+            let ast = Parser::new(pattern).with_dialect(dialect).parse();
+            let ast = ast.unwrap_or_else(|err| panic!("{:?} {}: {}", dialect, pattern, err));
+            assert_eq!(printer.print(&ast), expected, "{:?} {}", dialect, pattern);
+        }
+
+        let unsupported = vec![
+            (Dialect::Re2, "(?<=a)b", Feature::Lookbehind),
+            (Dialect::Re2, "a(?!b)", Feature::Lookahead),
+            (Dialect::Re2, "(a)\\1", Feature::Backreference),
+            (Dialect::Re2, "(?P<x>a)(?P=x)", Feature::Backreference),
+            (Dialect::Python, "(?<=a+)b", Feature::VariableLengthLookbehind),
+            (Dialect::Python, "\\p{L}", Feature::UnicodeCategory),
+            (Dialect::Python, "a(?i)b", Feature::UnscopedFlags),
+            (Dialect::Pcre, "(?<=ab|c+)d", Feature::VariableLengthLookbehind),
+            (Dialect::Pcre, "\\u0041", Feature::UnicodeEscape),
+            (Dialect::Java, "(?<=a*)b", Feature::VariableLengthLookbehind),
+            (Dialect::JavaScript, "(?i)a", Feature::UnscopedFlags),
+        ];
+        for (dialect, pattern, feature) in unsupported {
+            let err = Parser::new(pattern).with_dialect(dialect).parse().unwrap_err();
+            assert_eq!(err.kind, ParseErrorKind::Unsupported(feature, dialect), "{:?} {}", dialect, pattern);
+        }
+
+        // Each dialect's own group syntax only
+        assert!(Parser::new("(?P<a>x)").parse().is_err());
+        assert!(Parser::new("(?<a>x)").with_dialect(Dialect::Python).parse().is_err());
+        assert!(Parser::new("(a)\\k<a>").with_dialect(Dialect::Python).parse().is_err());
+        assert_eq!("go".parse::<Dialect>(), Ok(Dialect::Re2));
+    }
+
+    fn parse_error(dialect: Dialect, pattern: &str) -> ParseErrorKind {
+        match Parser::new(pattern).with_dialect(dialect).parse() {
+            Ok(ast) => panic!("{:?} {} parsed to {:?}", dialect, pattern, ast),
+            Err(err) => err.kind,
+        }
+    }
+
+    #[test]
+    fn test_generic_rejects() {
+        let dialect = Dialect::Generic;
+        for pattern in ["\\Aa", "a\\z", "a\\Z", "\\Ba", "\\Qa.b\\E", "a\\Kb", "\\cA", "[\\A]"] {
+            assert_eq!(parse_error(dialect, pattern), ParseErrorKind::InvalidEscape, "{}", pattern);
+        }
+        assert_eq!(parse_error(dialect, "(?i-q)a"), ParseErrorKind::InvalidFlag);
+        assert_eq!(parse_error(dialect, "(?P<a>x)"), ParseErrorKind::InvalidGroupSyntax);
+        assert_eq!(parse_error(dialect, "\\u0041"), ParseErrorKind::InvalidUnicodeValue);
+        // Symbols still escape to themselves
+        assert_eq!(Parser::new("\\.\\-").parse().unwrap(), vec![
+            RegexNode::new_literal('.'),
+            RegexNode::new_literal('-'),
+        ]);
+    }
+
+    #[test]
+    fn test_pcre_rejects() {
+        let dialect = Dialect::Pcre;
+        for pattern in ["\\Bfoo\\z", "\\Aabc\\Z", "\\Qa.b\\E", "a\\Kb", "\\cA", "\\G"] {
+            assert_eq!(parse_error(dialect, pattern), ParseErrorKind::InvalidEscape, "{}", pattern);
+        }
+        assert_eq!(parse_error(dialect, "\\u{41}"), ParseErrorKind::Unsupported(Feature::UnicodeEscape, dialect));
+        assert_eq!(parse_error(dialect, "(?<=a|bc*)d"), ParseErrorKind::Unsupported(Feature::VariableLengthLookbehind, dialect));
+        assert_eq!(parse_error(dialect, "(?u)a"), ParseErrorKind::InvalidFlag);
+    }
+
+    #[test]
+    fn test_javascript_rejects() {
+        let dialect = Dialect::JavaScript;
+        for pattern in ["\\cA", "a\\B", "[\\cJ]"] {
+            assert_eq!(parse_error(dialect, pattern), ParseErrorKind::InvalidEscape, "{}", pattern);
+        }
+        assert_eq!(parse_error(dialect, "a(?i)b"), ParseErrorKind::Unsupported(Feature::UnscopedFlags, dialect));
+        assert_eq!(parse_error(dialect, "(?>a)"), ParseErrorKind::Unsupported(Feature::AtomicGroup, dialect));
+        assert_eq!(parse_error(dialect, "a++"), ParseErrorKind::Unsupported(Feature::PossessiveQuantifier, dialect));
+        assert_eq!(parse_error(dialect, "(?x:a)"), ParseErrorKind::InvalidFlag);
+        assert_eq!(parse_error(dialect, "\\pL"), ParseErrorKind::InvalidUnicodeCategory);
+        assert_eq!(parse_error(dialect, "(?P<a>x)"), ParseErrorKind::InvalidGroupSyntax);
+    }
+
+    #[test]
+    fn test_python_rejects() {
+        let dialect = Dialect::Python;
+        for pattern in ["\\Aabc\\Z", "a\\B", "\\z"] {
+            assert_eq!(parse_error(dialect, pattern), ParseErrorKind::InvalidEscape, "{}", pattern);
+        }
+        assert_eq!(parse_error(dialect, "\\p{L}"), ParseErrorKind::Unsupported(Feature::UnicodeCategory, dialect));
+        assert_eq!(parse_error(dialect, "(?<=a|bc)d"), ParseErrorKind::Unsupported(Feature::VariableLengthLookbehind, dialect));
+        assert_eq!(parse_error(dialect, "a(?m)b"), ParseErrorKind::Unsupported(Feature::UnscopedFlags, dialect));
+        assert_eq!(parse_error(dialect, "(?U)a"), ParseErrorKind::InvalidFlag);
+        assert_eq!(parse_error(dialect, "(?'a'x)"), ParseErrorKind::InvalidGroupSyntax);
+    }
+
+    #[test]
+    fn test_re2_rejects() {
+        let dialect = Dialect::Re2;
+        for pattern in ["\\Aa\\z", "\\Ba", "\\Qa.b\\E", "\\C"] {
+            assert_eq!(parse_error(dialect, pattern), ParseErrorKind::InvalidEscape, "{}", pattern);
+        }
+        assert_eq!(parse_error(dialect, "(?<!a)b"), ParseErrorKind::Unsupported(Feature::Lookbehind, dialect));
+        assert_eq!(parse_error(dialect, "a(?=b)"), ParseErrorKind::Unsupported(Feature::Lookahead, dialect));
+        assert_eq!(parse_error(dialect, "(?P<x>a)\\1"), ParseErrorKind::Unsupported(Feature::Backreference, dialect));
+        assert_eq!(parse_error(dialect, "(?>a)"), ParseErrorKind::Unsupported(Feature::AtomicGroup, dialect));
+        assert_eq!(parse_error(dialect, "a*+"), ParseErrorKind::Unsupported(Feature::PossessiveQuantifier, dialect));
+        assert_eq!(parse_error(dialect, "(?x)a"), ParseErrorKind::InvalidFlag);
+    }
+
+    #[test]
+    fn test_dotnet_rejects() {
+        let dialect = Dialect::DotNet;
+        for pattern in ["\\Aa\\z", "a\\Z", "\\Ga", "\\cA", "\\Ba"] {
+            assert_eq!(parse_error(dialect, pattern), ParseErrorKind::InvalidEscape, "{}", pattern);
+        }
+        assert_eq!(parse_error(dialect, "a?+"), ParseErrorKind::Unsupported(Feature::PossessiveQuantifier, dialect));
+        assert_eq!(parse_error(dialect, "(?U)a"), ParseErrorKind::InvalidFlag);
+        assert_eq!(parse_error(dialect, "(?u)a"), ParseErrorKind::InvalidFlag);
+        assert_eq!(parse_error(dialect, "(?P<a>x)"), ParseErrorKind::InvalidGroupSyntax);
+    }
+
+    #[test]
+    fn test_java_rejects() {
+        let dialect = Dialect::Java;
+        for pattern in ["\\Aa\\z", "a\\Z", "\\Qa.b\\E", "\\cA", "\\R", "\\Ba"] {
+            assert_eq!(parse_error(dialect, pattern), ParseErrorKind::InvalidEscape, "{}", pattern);
+        }
+        assert_eq!(parse_error(dialect, "(?<=a+)b"), ParseErrorKind::Unsupported(Feature::VariableLengthLookbehind, dialect));
+        // A width past usize::MAX counts as unbounded
+        let huge = "(?<=((a{1000000000}){1000000000}){1000000000})b";
+        assert_eq!(parse_error(dialect, huge), ParseErrorKind::Unsupported(Feature::VariableLengthLookbehind, dialect));
+        assert_eq!(parse_error(dialect, "(?U)a"), ParseErrorKind::InvalidFlag);
+        assert_eq!(parse_error(dialect, "(?n)a"), ParseErrorKind::InvalidFlag);
+        assert_eq!(parse_error(dialect, "(?'a'x)"), ParseErrorKind::InvalidGroupSyntax);
+    }
+
+    #[test]
+    fn test_posix_ere_rejects() {
+        let dialect = Dialect::PosixEre;
+        assert_eq!(parse_error(dialect, "(?:a)"), ParseErrorKind::Unsupported(Feature::NonCapturingGroup, dialect));
+        assert_eq!(parse_error(dialect, "a(?=b)"), ParseErrorKind::Unsupported(Feature::Lookahead, dialect));
+        assert_eq!(parse_error(dialect, "\\d"), ParseErrorKind::Unsupported(Feature::CharacterType, dialect));
+        assert_eq!(parse_error(dialect, "a*?"), ParseErrorKind::Unsupported(Feature::LazyQuantifier, dialect));
+        assert_eq!(parse_error(dialect, "(?i)a"), ParseErrorKind::Unsupported(Feature::InlineFlags, dialect));
+    }
+
+    #[test]
+    fn test_dotnet_group_numbers() {
+        // .NET numbers `(b)` first, as named groups come after unnamed ones
+        let ast = Parser::new("(?<x>a)(b)\\1").with_dialect(Dialect::DotNet).parse().unwrap();
+        assert_eq!(ast[2], RegexNode::new_backreference(BackreferenceKind::NumberBased(2)));
+        assert_eq!(Matcher::new(&ast).is_match("abb"), Ok(true));
+        assert_eq!(Matcher::new(&ast).is_match("aba"), Ok(false));
+        assert_eq!(Printer::new(false).with_dialect(Dialect::Pcre).print(&ast), "(?<x>a)(b)\\2");
+        assert_eq!(Printer::new(false).with_dialect(Dialect::DotNet).print(&ast), "(?<x>a)(b)\\1");
+
+        let ast = Parser::new("(?<x>a)\\2(b)").with_dialect(Dialect::DotNet).parse().unwrap();
+        assert_eq!(ast[1], RegexNode::new_backreference(BackreferenceKind::NumberBased(1)));
+        assert_eq!(parse_error(Dialect::DotNet, "(?<x>a)\\1(b)"), ParseErrorKind::InvalidBackreference);
+        assert_eq!(parse_error(Dialect::DotNet, "(a)\\2"), ParseErrorKind::InvalidBackreference);
+    }
+
+    #[test]
+    fn test_printer_round_trip() {
+        let printer = Printer::new(false);
+        for pattern in PARSER_CORPUS {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let printed = printer.print(&ast);
+            assert_eq!(&printed, pattern);
+            assert_eq!(Parser::new(&printed).parse().unwrap(), ast, "{} did not reparse", printed);
+        }
+    }
+
+    #[test]
+    fn test_printing_alternation_among_other_nodes() {
+        // Passes can leave an alternation next to other nodes, which the
+        // parser itself never does
+        let alternation = |a: char, b: char| {
+            RegexNode::new_alternation(vec![vec![RegexNode::new_literal(a)], vec![RegexNode::new_literal(b)]])
+        };
+        let test_cases = [
+            (vec![RegexNode::new_literal('x'), alternation('a', 'b')], "x(?:a|b)"),
+            (vec![alternation('a', 'b'), alternation('c', 'd')], "(?:a|b)(?:c|d)"),
+            (
+                vec![RegexNode::new_alternation(vec![
+                    vec![RegexNode::new_literal('x'), alternation('a', 'b')],
+                    vec![RegexNode::new_literal('c')],
+                ])],
+                "x(?:a|b)|c",
+            ),
+        ];
+
+        let printer = Printer::new(false);
+        let mut verifier = Verifier::with_seed(7);
+        for (ast, expected) in test_cases {
+            let printed = printer.print(&ast);
+            assert_eq!(printed, expected);
+            // It reads back with the group made explicit, which prints the same
+            let reparsed = Parser::new(&printed).parse().unwrap();
+            assert_eq!(printer.print(&reparsed), printed);
+            assert!(verifier.verify(&ast, &reparsed).is_ok(), "{}", printed);
+        }
+
+        let ere = Printer::new(false).with_dialect(Dialect::PosixEre);
+        let err = ere.try_print(&[RegexNode::new_literal('x'), alternation('a', 'b')]).unwrap_err();
+        assert_eq!(err, PrintError { feature: Feature::NonCapturingGroup, dialect: Dialect::PosixEre });
+    }
+
+    #[test]
+    fn test_dialect_printing() {
+        let print = |pattern: &str, dialect: Dialect| {
+            let ast = Parser::new(pattern).parse().unwrap();
+            Printer::new(false).with_dialect(dialect).try_print(&ast)
+        };
+        let test_cases = vec![
+            ("(?<n>a)\\k<n>", Dialect::Python, "(?P<n>a)(?P=n)"),
+            ("(?<n>a)\\k<n>", Dialect::Pcre, "(?<n>a)\\k<n>"),
+            ("(?i)ab", Dialect::JavaScript, "(?i:ab)"),
+            ("(?i)ab", Dialect::DotNet, "(?i)ab"),
+            ("\\v\\0", Dialect::Pcre, "\\x0B\\x00"),
+            ("[\\^a\\]\\-]", Dialect::PosixEre, "[]a^-]"),
+            ("a\\n\\}", Dialect::PosixEre, "a\n}"),
+        ];
+        for (pattern, dialect, expected) in test_cases {
+            assert_eq!(print(pattern, dialect), Ok(expected.to_string()), "{} in {:?}", pattern, dialect);
+        }
+
+        let ast = Parser::new("é😀").parse().unwrap();
+        let escapes = vec![
+            (Dialect::Generic, "\\u{E9}\\u{1F600}"),
+            (Dialect::JavaScript, "\\u{E9}\\u{1F600}"),
+            (Dialect::Python, "\\u00E9\\U0001F600"),
+            (Dialect::Pcre, "\\xE9\\x{1F600}"),
+            (Dialect::Java, "\\u00E9\\x{1F600}"),
+            (Dialect::DotNet, "\\u00E9(?:\\uD83D\\uDE00)"),
+        ];
+        for (dialect, expected) in escapes {
+            assert_eq!(Printer::new(true).with_dialect(dialect).print(&ast), expected);
+        }
+
+        let unsupported = vec![
+            ("(?<=a)b", Dialect::Re2, Feature::Lookbehind),
+            ("(?<=a|bc)d", Dialect::Python, Feature::VariableLengthLookbehind),
+            ("(a)\\1", Dialect::PosixEre, Feature::Backreference),
+            ("(?:a)", Dialect::PosixEre, Feature::NonCapturingGroup),
+            ("a*?", Dialect::PosixEre, Feature::LazyQuantifier),
+            ("\\p{L}", Dialect::Python, Feature::UnicodeCategory),
+            ("[😀a]", Dialect::DotNet, Feature::AstralCharInClass),
+        ];
+        for (pattern, dialect, feature) in unsupported {
+            assert_eq!(print(pattern, dialect), Err(PrintError { feature, dialect }), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_dialect_round_trip() {
+        // Whatever a dialect prints, it reads back the same
+        for dialect in Dialect::ALL {
+            for escapes in [false, true] {
+                let printer = Printer::new(escapes).with_dialect(dialect);
+                for pattern in PARSER_CORPUS.iter().chain(&["\\x41\\u{E9}\\t", "[\\]a-c\\-]"]) {
+                    let ast = Parser::new(pattern).parse().unwrap();
+                    let Ok(printed) = printer.try_print(&ast) else {
+                        continue;
+                    };
+                    let reparsed = Parser::new(&printed).with_dialect(dialect).parse();
+                    let reparsed = reparsed.unwrap_or_else(|err| panic!("{:?} {}: {}", dialect, printed, err));
+                    assert_eq!(printer.print(&reparsed), printed, "{:?}", dialect);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_posix_classes_reparse_to_same_chars() {
+        let printer = Printer::new(false).with_dialect(Dialect::PosixEre);
+        let patterns = [
+            "[-^]", "[\\^-a]", "[\\^-_]", "[\\^-\\^]", "[\\^\\[]", "[-\\[\\^]", "[\\]^-]", "[^-^]", "[\\^-ax]",
+        ];
+        for pattern in patterns {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let printed = printer.print(&ast);
+            let reparsed = Parser::new(&printed).with_dialect(Dialect::PosixEre).parse().unwrap();
+            for c in ['^', '-', '[', ']', '_', '`', 'a', 'x', 'z'] {
+                let expected = Matcher::new(&ast).is_match(&c.to_string());
+                let found = Matcher::new(&reparsed).is_match(&c.to_string());
+                assert_eq!(found, expected, "{} as {} on {:?}", pattern, printed, c);
+            }
+        }
+        let translated = translate::translate(&Parser::new("[-^]").parse().unwrap(), Dialect::PosixEre).unwrap();
+        assert_eq!(printer.print(&translated), "[-^]");
+    }
+
+    #[test]
+    fn test_obfuscating_for_a_dialect() {
+        // Paranoid adds lookaround noise, which RE2 and Python can't always print
+        for dialect in [Dialect::Re2, Dialect::Python, Dialect::PosixEre] {
+            let printer = Printer::new(false).with_dialect(dialect);
+            let ast = Parser::new("ab[c-e]+.").parse().unwrap();
+            for seed in 0..5 {
+                let mut obfuscator =
+                    Obfuscator::builder().intensity(Intensity::Paranoid).dialect(dialect).seed(seed).build();
+                let obfuscated = obfuscator.obfuscate(ast.clone());
+                assert!(printer.try_print(&obfuscated).is_ok(), "{:?} {:?}", dialect, obfuscated);
+            }
+        }
+    }
+
+    #[test]
+    fn test_obfuscating_possessive_quantifiers() {
+        let ast = Parser::new("a++b(?>cd|c)d").parse().unwrap();
+        let mut verifier = Verifier::with_seed(3);
+        let mut rewritten = false;
+        for seed in 0..10 {
+            let obfuscated = Obfuscator::builder().seed(seed).build().obfuscate(ast.clone());
+            assert!(verifier.verify(&ast, &obfuscated).is_ok(), "{:?}", obfuscated);
+            // `a++` → `(?>a+)`, next to the atomic group already there
+            rewritten |= Printer::new(false).print(&obfuscated).matches("(?>").count() == 2;
+        }
+        assert!(rewritten);
+    }
+
+    #[test]
+    fn test_translating_between_dialects() {
+        let translate_to = |pattern: &str, source: Dialect, target: Dialect| {
+            let ast = Parser::new(pattern).with_dialect(source).parse().unwrap();
+            let translated = translate::translate(&ast, target).unwrap();
+            Printer::new(false).with_dialect(target).print(&translated)
+        };
+
+        let test_cases = [
+            ("\\d+\\w", Dialect::PosixEre, "[0-9]+[a-zA-Z0-9_]"),
+            ("[\\d_]x", Dialect::PosixEre, "[0-9_]x"),
+            ("[^\\D]", Dialect::PosixEre, "[0-9]"),
+            ("(?<year>\\d{4})-(?:ab|c)(?:d)*", Dialect::PosixEre, "([0-9]{4})-(ab|c)d*"),
+            ("(?i)ab[a-c]", Dialect::PosixEre, "[aA][bB][a-cA-C]"),
+            ("(?i:a|b)c", Dialect::PosixEre, "([aA]|[bB])c"),
+            ("(?<n>a)\\k<n>", Dialect::Python, "(?P<n>a)(?P=n)"),
+            ("[a\\u{1F600}]", Dialect::DotNet, "(?:a|\u{1F600})"),
+            ("a++b", Dialect::DotNet, "(?>a+)b"),
+        ];
+        for (pattern, target, expected) in test_cases {
+            assert_eq!(translate_to(pattern, Dialect::Generic, target), expected, "{}", pattern);
+        }
+        assert_eq!(translate_to("(?P<n>a)(?P=n)", Dialect::Python, Dialect::JavaScript), "(?<n>a)\\k<n>");
+
+        // Lowering keeps the meaning
+        let mut verifier = Verifier::with_seed(7);
+        for pattern in ["\\w+@\\d", "(?i)[x-z]+Q", "[^\\s]b"] {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let translated = translate::translate(&ast, Dialect::PosixEre).unwrap();
+            assert!(verifier.verify(&ast, &translated).is_ok(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_untranslatable_constructs() {
+        let ast = Parser::new("(a)\\1(?=b)\\p{L}").parse().unwrap();
+        assert_eq!(
+            translate::translate(&ast, Dialect::Re2),
+            Err(Untranslatable {
+                dialect: Dialect::Re2,
+                features: vec![Feature::Backreference, Feature::Lookahead],
+            })
+        );
+        let err = translate::translate(&ast, Dialect::PosixEre).unwrap_err();
+        assert_eq!(err.to_string(), "POSIX ERE can't express backreferences, lookaheads or unicode categories");
+
+        // Lookbehind lengths are only checked where the dialect limits them
+        let ast = Parser::new("(?<=ab+)c").parse().unwrap();
+        assert!(translate::translate(&ast, Dialect::DotNet).is_ok());
+        assert_eq!(
+            translate::translate(&ast, Dialect::Python).unwrap_err().features,
+            vec![Feature::VariableLengthLookbehind]
+        );
+    }
+
+    #[test]
+    fn test_printer_escapes_metacharacters() {
+        let printer = Printer::new(false);
+        let test_cases = vec![
+            ("\\.", "\\."),
+            ("a\\*\\+\\?", "a\\*\\+\\?"),
+            ("\\(\\)\\[\\]\\{\\}", "\\(\\)\\[\\]\\{\\}"),
+            ("\\|\\^\\$\\\\", "\\|\\^\\$\\\\"),
+            ("[\\]\\\\\\^\\-]", "[\\]\\\\^-]"),
+            ("[\\^a]", "[\\^a]"),
+            ("[a\\-z]", "[a\\-z]"),
+            ("[.*+?(){}|$]", "[.*+?(){}|$]"),
+        ];
+
+        for (pattern, expected) in test_cases {
+            let ast = Parser::new(pattern).parse().unwrap();
+            assert_eq!(printer.print(&ast), expected);
+        }
+    }
+
+    #[test]
+    fn test_printed_patterns_reparse_to_same_ast() {
+        let printer = Printer::new(false);
+        let patterns = [
+            "[^\\d\\n\\]\\x00-\\x1F\\p{L}]",
+            "[\\^-a]",
+            "[!-\\-]",
+            "(a)\\1\\x30",
+            "\\.+?|[\\[\\]]{2,3}",
+            "(?=\\()\\w+(?<!\\))",
+        ];
+
+        for pattern in patterns {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let printed = printer.print(&ast);
+            assert_eq!(Parser::new(&printed).parse().unwrap(), ast, "{} did not reparse", printed);
+        }
+    }
+
+    #[test]
+    fn test_backreference_followed_by_digit() {
+        let ast = vec![
+            RegexNode::new_group(GroupKind::Capturing(None), vec![RegexNode::new_literal('a')]),
+            RegexNode::new_backreference(BackreferenceKind::NumberBased(1)),
+            RegexNode::new_literal('0'),
+        ];
+        assert_eq!(Printer::new(false).print(&ast), "(a)(?:\\1)0");
+    }
+
+    #[test]
+    fn test_parse_error_spans() {
+        let test_cases = vec![
+            ("a(b|c", ParseErrorKind::UnclosedGroup, Span { start: 1, end: 2 }),
+            ("ab[cd", ParseErrorKind::UnclosedCharacterClass, Span { start: 2, end: 3 }),
+            ("x[z-a]", ParseErrorKind::InvalidClassRange, Span { start: 2, end: 5 }),
+            ("(a)\\2", ParseErrorKind::InvalidBackreference, Span { start: 3, end: 5 }),
+            ("(a)\\99999999999999999999999", ParseErrorKind::InvalidBackreference, Span { start: 3, end: 27 }),
+            ("a\\u{FFFFFF}", ParseErrorKind::InvalidUnicodeValue, Span { start: 1, end: 11 }),
+            ("a||b", ParseErrorKind::EmptyAlternation, Span { start: 2, end: 3 }),
+            ("ab|", ParseErrorKind::EmptyAlternation, Span { start: 2, end: 3 }),
+            ("ab)", ParseErrorKind::UnexpectedCharacter(')'), Span { start: 2, end: 3 }),
+            ("a{2", ParseErrorKind::UnexpectedEndOfInput, Span { start: 3, end: 3 }),
+        ];
+
+        for (pattern, kind, span) in test_cases {
+            let err = Parser::new(pattern).parse().unwrap_err();
+            assert_eq!(err.kind, kind, "{}", pattern);
+            assert_eq!(err.span, span, "{}", pattern);
+        }
+
+        // Numbers too big to read are errors in every dialect
+        for dialect in Dialect::ALL {
+            let err = parse_error(dialect, "(a)\\99999999999999999999999");
+            let expected = if dialect.supports(Feature::Backreference) {
+                ParseErrorKind::InvalidBackreference
+            } else {
+                ParseErrorKind::Unsupported(Feature::Backreference, dialect)
+            };
+            assert_eq!(err, expected, "{:?}", dialect);
+        }
+
+        // Braced escapes past U+10FFFF, or of surrogates, aren't chars
+        let test_cases = [
+            (Dialect::JavaScript, "\\u{110000}"),
+            (Dialect::JavaScript, "\\u{D800}"),
+            (Dialect::Pcre, "\\x{110000}"),
+            (Dialect::Java, "[\\x{FFFFFF}]"),
+            (Dialect::Re2, "\\x{DFFF}"),
+        ];
+        for (dialect, pattern) in test_cases {
+            assert_eq!(parse_error(dialect, pattern), ParseErrorKind::InvalidUnicodeValue, "{}", pattern);
+        }
+        assert!(Parser::new("\\u{10FFFF}").parse().is_ok());
+    }
+
+    #[test]
+    fn test_parse_error_byte_span() {
+        let err = Parser::new("éé(").parse().unwrap_err();
+        assert_eq!(err.span, Span { start: 2, end: 3 });
+        assert_eq!(err.byte_span, Span { start: 4, end: 5 });
+    }
+
+    #[test]
+    fn test_parse_error_render() {
+        let err = Parser::new("a(b|c").parse().unwrap_err();
+        assert_eq!(err.to_string(), "unclosed group at position 1, expected `)`");
+        assert_eq!(
+            err.render("a(b|c"),
+            "error: unclosed group\n  |\n  | a(b|c\n  |  ^ expected `)`"
+        );
+    }
+
+    #[test]
+    fn test_obfuscated_dot_respects_dot_all() {
+        let printer = Printer::new(false);
+        let without_dot_all = ["[^\\n]", "[^\\x0A]", "(?:(?!\\n)[\\s\\S])"];
+        let with_dot_all = ["[\\s\\S]", "[\\d\\D]", "[\\w\\W]", "(?:\\n|[^\\n])"];
+
+        for _ in 0..20 {
+            let ast = Parser::new(".").parse().unwrap();
+            let printed = printer.print(&Obfuscator::new().obfuscate(ast));
+            assert!(without_dot_all.contains(&printed.as_str()), "{}", printed);
+
+            let ast = Parser::new("(?s:.)").parse().unwrap();
+            let printed = printer.print(&Obfuscator::new().obfuscate(ast));
+            let inner = printed.strip_prefix("(?s:").and_then(|p| p.strip_suffix(')')).unwrap();
+            assert!(with_dot_all.contains(&inner), "{}", printed);
+
+            // The flag only applies inside its group
+            let ast = Parser::new("(?s:a).").parse().unwrap();
+            let printed = printer.print(&Obfuscator::new().obfuscate(ast));
+            let dot = printed.strip_prefix("(?s:[a])").unwrap();
+            assert!(without_dot_all.contains(&dot), "{}", printed);
+        }
+    }
+
+    #[test]
+    fn test_obfuscated_quantifiers() {
+        let printer = Printer::new(false);
+        let test_cases = vec![
+            ("a*", vec!["[a]{0,}", "(?:[a]+)?"]),
+            ("a*?", vec!["[a]{0,}?", "(?:[a]+?)??"]),
+            ("a+?", vec!["[a]{1,}?", "(?:[a][a]*?)"]),
+            ("a?", vec!["[a]{0,1}"]),
+            ("a??", vec!["[a]{0,1}?"]),
+            ("a{3}", vec!["[a]{3,3}", "(?:[a][a]{2})", "(?:[a]{1}){3}"]),
+            ("a{2,}?", vec!["(?:[a]{2}[a]*?)"]),
+            ("a{2,5}?", vec!["(?:[a]{2}[a]{0,3}?)"]),
+        ];
+
+        for (pattern, allowed) in test_cases {
+            for _ in 0..20 {
+                let ast = Parser::new(pattern).parse().unwrap();
+                let printed = printer.print(&Obfuscator::new().obfuscate(ast));
+                assert!(allowed.contains(&printed.as_str()), "{} became {}", pattern, printed);
+            }
+        }
+
+        // Capturing groups are never duplicated
+        for pattern in ["(a)+", "(a){2}", "(a){2,}", "(a){1,3}"] {
+            for _ in 0..20 {
+                let ast = Parser::new(pattern).parse().unwrap();
+                let mut groups = Vec::new();
+                capture_groups(&Obfuscator::new().obfuscate(ast), &mut groups);
+                assert_eq!(groups.len(), 1, "{}", pattern);
+            }
+        }
+    }
+
+    // Capturing groups in numbering order, as (name, contents)
+    fn capture_groups(nodes: &[RegexNode], groups: &mut Vec<(Option<String>, Vec<RegexNode>)>) {
+        for node in nodes {
+            match node {
+                RegexNode::Group(kind, nodes) => {
+                    if let GroupKind::Capturing(name) = kind {
+                        groups.push((name.clone(), nodes.clone()));
+                    }
+                    capture_groups(nodes, groups);
+                }
+                RegexNode::Quantified { node, .. } => capture_groups(std::slice::from_ref(node), groups),
+                RegexNode::Lookaround(_, nodes) | RegexNode::FlagSet(_, _, nodes) => {
+                    capture_groups(nodes, groups)
+                }
+                RegexNode::Alternation(alternatives) => {
+                    alternatives.iter().for_each(|alt| capture_groups(alt, groups))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn backreferences(nodes: &[RegexNode], refs: &mut Vec<BackreferenceKind>) {
+        for node in nodes {
+            match node {
+                RegexNode::Backreference(kind) => refs.push(kind.clone()),
+                RegexNode::Quantified { node, .. } => backreferences(std::slice::from_ref(node), refs),
+                RegexNode::Group(_, nodes)
+                | RegexNode::Lookaround(_, nodes)
+                | RegexNode::FlagSet(_, _, nodes) => backreferences(nodes, refs),
+                RegexNode::Alternation(alternatives) => {
+                    alternatives.iter().for_each(|alt| backreferences(alt, refs))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_obfuscated_backreferences_keep_their_groups() {
+        let printer = Printer::new(false);
+        let test_cases = vec![
+            ("(a)(?<x>b)(c)\\3\\1\\k<x>\\2", vec!["a", "b", "c"], vec!["c", "a", "b", "b"]),
+            ("(?=(a))(b)\\2\\1", vec!["a", "b"], vec!["b", "a"]),
+        ];
+
+        for (pattern, expected_groups, expected_refs) in test_cases {
+            for _ in 0..20 {
+                let ast = Parser::new(pattern).parse().unwrap();
+                let obfuscated = Obfuscator::new().obfuscate(ast);
+                let printed = printer.print(&obfuscated);
+                assert_eq!(Parser::new(&printed).parse().unwrap(), obfuscated, "{}", printed);
+
+                let mut groups = Vec::new();
+                capture_groups(&obfuscated, &mut groups);
+                let contents = |nodes: &[RegexNode]| printer.print(nodes).replace(['[', ']'], "");
+                let group_contents: Vec<String> = groups.iter().map(|(_, nodes)| contents(nodes)).collect();
+                assert_eq!(group_contents, expected_groups, "{}", printed);
+
+                let mut refs = Vec::new();
+                backreferences(&obfuscated, &mut refs);
+                let resolved: Vec<String> = refs
+                    .iter()
+                    .map(|kind| {
+                        let (_, nodes) = match kind {
+                            BackreferenceKind::NumberBased(n) => &groups[n - 1],
+                            BackreferenceKind::NameBased(name) => groups
+                                .iter()
+                                .find(|(group_name, _)| group_name.as_ref() == Some(name))
+                                .unwrap(),
+                        };
+                        contents(nodes)
+                    })
+                    .collect();
+                assert_eq!(resolved, expected_refs, "{}", printed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_obfuscated_lookaround_bodies() {
+        let printer = Printer::new(false);
+        for pattern in ["(?=abc)", "(?!abc)", "(?<=abc)", "(?<!abc)"] {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let printed = printer.print(&Obfuscator::new().obfuscate(ast));
+            assert!(!printed.contains("abc"), "{} became {}", pattern, printed);
+            assert!(printed.contains("[a][b][c]"), "{} became {}", pattern, printed);
+        }
+    }
+
+    #[test]
+    fn test_lookaround_noise() {
+        let printer = Printer::new(false);
+        let neutral = ["(?=[\\s\\S]|$)", "(?<=[\\s\\S]|^)", "(?![^\\s\\S])"];
+
+        let ast = Parser::new("a").parse().unwrap();
+        let printed = printer.print(&Obfuscator::new().with_lookaround_noise(1.0).obfuscate(ast));
+        let literal = neutral
+            .iter()
+            .find_map(|assertion| printed.strip_prefix(assertion))
+            .unwrap_or_else(|| panic!("no neutral assertion in {}", printed));
+        assert_eq!(literal, "(?:(?=[a])[a])");
+
+        // Lookaround bodies never get extra assertions
+        let ast = Parser::new("(?<=ab)").parse().unwrap();
+        let obfuscated = Obfuscator::new().with_lookaround_noise(1.0).obfuscate(ast);
+        match obfuscated.last() {
+            Some(RegexNode::Lookaround(_, nodes)) => {
+                assert_eq!(printer.print(nodes), "[a][b]");
+            }
+            other => panic!("expected a lookbehind, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_seeded_obfuscation_is_reproducible() {
+        let printer = Printer::new(false);
+        let pattern = "(a)(?<x>[b-y]+)\\1.{2,5}?(?=z|\\d*)";
+        let run = |seed: u64| {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let mut obfuscator = Obfuscator::with_seed(seed).with_lookaround_noise(0.3);
+            printer.print(&obfuscator.obfuscate(ast))
+        };
+
+        assert_eq!(run(42), run(42));
+        assert!((0..10).any(|seed| run(seed) != run(42)));
+    }
+
+    struct UppercaseLiterals;
+
+    impl Pass for UppercaseLiterals {
+        fn name(&self) -> &str {
+            "uppercase"
+        }
+
+        fn rewrite(&mut self, node: RegexNode, ctx: &mut PassContext) -> RegexNode {
+            match node {
+                RegexNode::Literal(c) if ctx.chance() => RegexNode::Literal(c.to_ascii_uppercase()),
+                node => node,
+            }
+        }
+    }
+
+    struct MarkEveryNode;
+
+    impl Pass for MarkEveryNode {
+        fn name(&self) -> &str {
+            "mark"
+        }
+
+        fn rewrite(&mut self, node: RegexNode, _ctx: &mut PassContext) -> RegexNode {
+            node
+        }
+
+        fn insert_before(&mut self, _next: &RegexNode, _ctx: &mut PassContext) -> Option<RegexNode> {
+            Some(RegexNode::new_literal('!'))
+        }
+    }
+
+    #[test]
+    fn test_obfuscator_builder() {
+        let printer = Printer::new(false);
+        let run = |obfuscator: &mut Obfuscator, pattern: &str| {
+            let ast = Parser::new(pattern).parse().unwrap();
+            printer.print(&obfuscator.obfuscate(ast))
+        };
+
+        // No passes, no change
+        let mut obfuscator = Obfuscator::builder().clear().build();
+        assert_eq!(run(&mut obfuscator, "(a)[b-z].+\\1"), "(a)[b-z].+\\1");
+
+        // Passes with a weight of zero never fire
+        let mut obfuscator = Obfuscator::builder()
+            .weight("class-alternation", 0.0)
+            .weight("dot", 0.0)
+            .weight("quantifier", 0.0)
+            .weight("group", 0.0)
+            .build();
+        assert_eq!(run(&mut obfuscator, "(a)[b-z].+\\1"), "([a])[b-z].+\\1");
+
+        let mut obfuscator = Obfuscator::builder()
+            .disable("literal-class")
+            .disable("quantifier")
+            .seed(7)
+            .build();
+        assert_eq!(run(&mut obfuscator, "ab*"), "ab*");
+
+        // Custom passes run in the order they were added
+        let mut obfuscator = Obfuscator::builder()
+            .clear()
+            .pass(UppercaseLiterals)
+            .pass(LiteralToClass)
+            .build();
+        assert_eq!(run(&mut obfuscator, "ab"), "[A][B]");
+
+        // A node inserted in front of an alternation doesn't join its first branch
+        let mut obfuscator = Obfuscator::builder().clear().pass(MarkEveryNode).build();
+        assert_eq!(run(&mut obfuscator, "(?>a|ab)c"), "!(?>!(?:!a|!a!b))!c");
+        assert_eq!(run(&mut obfuscator, "a|b"), "!(?:!a|!b)");
+    }
+
+    /// Runs `pattern` on `haystack` and returns the text of the whole match
+    /// followed by each group's.
+    fn find_texts(pattern: &str, haystack: &str) -> Option<Vec<Option<String>>> {
+        let ast = Parser::new(pattern).parse().unwrap();
+        let found = Matcher::new(&ast).find(haystack).unwrap()?;
+        let text = |span: Option<Span>| span.map(|span| Match::text(haystack, span));
+        let mut texts = vec![text(Some(found.span()))];
+        texts.extend(found.groups().iter().map(|&span| text(span)));
+        Some(texts)
+    }
+
+    fn find_text(pattern: &str, haystack: &str) -> Option<String> {
+        find_texts(pattern, haystack).map(|texts| texts[0].clone().unwrap())
+    }
+
+    #[test]
+    fn test_matcher_chars() {
+        assert_eq!(find_text("b[a-c]d", "xxbcd"), Some("bcd".to_string()));
+        assert_eq!(find_text("[^a-c]", "abcd"), Some("d".to_string()));
+        assert_eq!(find_text("\\d\\s\\w", "a 1 _"), Some("1 _".to_string()));
+        assert_eq!(find_text("[\\d\\-]+", "x1-2y"), Some("1-2".to_string()));
+        assert_eq!(find_text("\\p{L}\\P{L}", "1aé!"), Some("é!".to_string()));
+        assert_eq!(find_text("\\x41\\u{263A}", "A☺"), Some("A☺".to_string()));
+        assert_eq!(find_text("a.c", "a\nc"), None);
+        assert_eq!(find_text("(?s)a.c", "a\nc"), Some("a\nc".to_string()));
+        assert_eq!(find_text("(?i)[a-c]B", "Cb"), Some("Cb".to_string()));
+        assert_eq!(find_text("(?i:a)a", "AA"), None);
+    }
+
+    #[test]
+    fn test_matcher_assertions() {
+        assert_eq!(find_text("^b", "ab"), None);
+        assert_eq!(find_text("(?m)^b$", "a\nb\nc"), Some("b".to_string()));
+        assert_eq!(find_text("a$", "a\n"), None);
+        assert_eq!(find_text("\\bcat\\b", "concat cat"), Some("cat".to_string()));
+        assert_eq!(find_texts("\\bcat\\b", "concat cats"), None);
+        assert_eq!(find_text("(?<=\\$)\\d+", "a1 $23"), Some("23".to_string()));
+        assert_eq!(find_text("(?<!\\$)\\b\\d+", "$1 23"), Some("23".to_string()));
+        assert_eq!(find_text("a(?=b)", "acab"), Some("a".to_string()));
+        assert_eq!(find_text("a(?!b)", "abac"), Some("a".to_string()));
+        assert_eq!(find_texts("(?=(a+))a", "aaa"), Some(vec![Some("a".to_string()), Some("aaa".to_string())]));
+    }
+
+    #[test]
+    fn test_matcher_quantifiers() {
+        assert_eq!(find_text("a*", "aaa"), Some("aaa".to_string()));
+        assert_eq!(find_text("a*?", "aaa"), Some("".to_string()));
+        assert_eq!(find_text("a+?", "aaa"), Some("a".to_string()));
+        assert_eq!(find_text("a??b", "ab"), Some("ab".to_string()));
+        assert_eq!(find_text("a{2}", "aaa"), Some("aa".to_string()));
+        assert_eq!(find_text("a{2,}", "aaaa"), Some("aaaa".to_string()));
+        assert_eq!(find_text("a{2,}?", "aaaa"), Some("aa".to_string()));
+        assert_eq!(find_text("a{1,3}", "aaaa"), Some("aaa".to_string()));
+        assert_eq!(find_text("a{1,3}?b", "aaab"), Some("aaab".to_string()));
+        assert_eq!(find_text("<.+>", "<a><b>"), Some("<a><b>".to_string()));
+        assert_eq!(find_text("<.+?>", "<a><b>"), Some("<a>".to_string()));
+        // Repetitions of something that can match empty still end
+        assert_eq!(find_text("(?:a*)*b", "aab"), Some("aab".to_string()));
+        assert_eq!(find_text("(?:a?)+", "aa"), Some("aa".to_string()));
+        // Long haystacks backtrack without running out of stack
+        let long = "a".repeat(20_000);
+        assert_eq!(find_text("a*", &long), Some(long.clone()));
+        assert_eq!(find_text("(?:a|b)*?$", &long), Some(long.clone()));
+        let texts = find_texts("(a)*a", &long).unwrap();
+        assert_eq!(texts[0], Some(long.clone()));
+        assert_eq!(texts[1], Some("a".to_string()));
+    }
+
+    #[test]
+    fn test_matcher_groups() {
+        let texts = |items: &[Option<&str>]| Some(items.iter().map(|t| t.map(String::from)).collect());
+
+        assert_eq!(find_texts("(a)(b)?c", "ac"), texts(&[Some("ac"), Some("a"), None]));
+        assert_eq!(find_texts("(a|ab)(c|bcd)", "abcd"), texts(&[Some("abcd"), Some("a"), Some("bcd")]));
+        assert_eq!(find_texts("(?:(a)|b)+", "ab"), texts(&[Some("ab"), Some("a")]));
+        assert_eq!(find_texts("(\\w)\\1", "abccd"), texts(&[Some("cc"), Some("c")]));
+        assert_eq!(find_texts("(?i)(a)\\1", "aA"), texts(&[Some("aA"), Some("a")]));
+        assert_eq!(find_texts("(?<x>.)(.)\\k<x>", "abab"), texts(&[Some("aba"), Some("a"), Some("b")]));
+        // A group that didn't take part never matches a reference to it
+        assert_eq!(find_texts("(a)?\\1b", "b"), None);
+
+        let ast = Parser::new("(?<year>\\d{4})-(?<month>\\d{2})").parse().unwrap();
+        let found = Matcher::new(&ast).find("on 2024-05").unwrap().unwrap();
+        assert_eq!(found.span(), Span { start: 3, end: 10 });
+        assert_eq!(found.name("month"), Some(Span { start: 8, end: 10 }));
+        assert_eq!(found.name("day"), None);
+    }
+
+    #[test]
+    fn test_matcher_step_limit() {
+        let ast = Parser::new("(a+)+b").parse().unwrap();
+        let matcher = Matcher::new(&ast).with_step_limit(10_000);
+        assert_eq!(matcher.find(&"a".repeat(30)), Err(MatchError::StepLimitExceeded));
+        assert!(matcher.is_match("aab").unwrap());
+    }
+
+    #[test]
+    fn test_obfuscated_patterns_match_the_same() {
+        let haystacks = ["", "abc", "ABC abc", "a\nb", "x1-2_y", "aaab", "The cat sat."];
+        for pattern in PARSER_CORPUS {
+            let ast = Parser::new(pattern).parse().unwrap();
+            for seed in 0..5 {
+                let obfuscated = Obfuscator::with_seed(seed)
+                    .with_lookaround_noise(0.3)
+                    .obfuscate(ast.clone());
+                for haystack in haystacks {
+                    let expected = Matcher::new(&ast).find(haystack).unwrap();
+                    let actual = Matcher::new(&obfuscated).find(haystack).unwrap();
+                    assert_eq!(
+                        actual.as_ref().map(|m| (m.span(), m.groups().to_vec())),
+                        expected.as_ref().map(|m| (m.span(), m.groups().to_vec())),
+                        "{} became {} on {:?}",
+                        pattern,
+                        Printer::new(false).print(&obfuscated),
+                        haystack,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_verifier_accepts_obfuscated_output() {
+        for (seed, pattern) in PARSER_CORPUS.iter().enumerate() {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let obfuscated = Obfuscator::with_seed(seed as u64)
+                .with_lookaround_noise(0.3)
+                .obfuscate(ast.clone());
+            let report = Verifier::with_seed(seed as u64)
+                .with_samples(50)
+                .verify(&ast, &obfuscated)
+                .unwrap_or_else(|mismatch| panic!("{}: {}", pattern, mismatch));
+            assert!(report.checked > 0);
+        }
+    }
+
+    #[test]
+    fn test_verifier_finds_mismatches() {
+        for (original, changed) in [("a+", "a*"), ("(a)(b)", "(a)b"), ("a.c", "(?s)a.c"), ("\\d+x", "\\d+?x?")] {
+            let original = Parser::new(original).parse().unwrap();
+            let changed = Parser::new(changed).parse().unwrap();
+            let mismatch = Verifier::with_seed(1).verify(&original, &changed).expect_err(&format!("{:?}", original));
+
+            let expected = Matcher::new(&original).find(&mismatch.input).unwrap();
+            assert_eq!(mismatch.original, expected);
+            assert_ne!(mismatch.original, mismatch.obfuscated);
+        }
+    }
+
+    #[test]
+    fn test_generated_samples_match() {
+        let patterns = [
+            "[^abc]{3}", "[^\\s\\d]+", "\\D\\W\\S", "\\P{L}\\p{N}", "(a|bc)\\1", "(?<q>['\"]).*\\k<q>",
+            "(?i)abc", "(?s)a.c", "\\x41\\u{263A}[\\d\\-]+", "(?:a|b|c|d)|e",
+        ];
+        let corpus = PARSER_CORPUS.iter().filter(|pattern| !pattern.contains("(?"));
+        for pattern in patterns.iter().chain(corpus) {
+            let ast = Parser::new(pattern).parse().unwrap();
+            // ^(?:pattern)$, so lazy quantifiers still have to cover the sample
+            let anchored = vec![
+                RegexNode::new_anchor(AnchorType::Start),
+                RegexNode::new_group(GroupKind::NonCapturing, ast.clone()),
+                RegexNode::new_anchor(AnchorType::End),
+            ];
+            let matcher = Matcher::new(&anchored);
+            let mut generator = Generator::with_seed(3).with_max_repeat(2);
+            for _ in 0..20 {
+                let sample = generator.sample(&ast);
+                assert!(matcher.is_match(&sample).unwrap(), "{} did not match sample {:?}", pattern, sample);
+            }
+        }
+    }
+
+    #[test]
+    fn test_generator_is_reproducible() {
+        let ast = Parser::new("(\\w+)@[a-z]{2,}\\.(?:com|org)").parse().unwrap();
+        let run = |seed: u64| {
+            let mut generator = Generator::with_seed(seed).with_max_length(5);
+            let strings = [generator.sample(&ast), generator.near_miss(&ast), generator.random_string(&ast)];
+            assert!(strings[2].chars().count() <= 5);
+            strings
+        };
+        assert_eq!(run(11), run(11));
+        assert_ne!(run(11), run(12));
+
+        // Capped quantifiers never run past min + max_repeat
+        let ast = Parser::new("a{2,}b*").parse().unwrap();
+        let mut generator = Generator::with_seed(0).with_max_repeat(1);
+        for _ in 0..20 {
+            assert!(generator.sample(&ast).len() <= 4);
+        }
+
+        // Samples stop growing at the length cap, however big the minimum
+        let mut generator = Generator::with_seed(0).with_max_sample_length(50);
+        for pattern in ["a{999999}", "(?:ab{3}){999999}", "((a{1000}){1000}){1000}", "(?:(?:){999999}){999999}"] {
+            let ast = Parser::new(pattern).parse().unwrap();
+            assert!(generator.sample(&ast).len() <= 53, "{}", pattern);
+        }
+        let ast = Parser::new("a{999999}").parse().unwrap();
+        assert!(Verifier::with_seed(0).with_samples(5).verify(&ast, &ast).is_ok());
+    }
+
+    #[test]
+    fn test_nfa_matches_like_the_backtracker() {
+        let haystacks = ["", "abc", "ABC abc", "a\nb", "x1-2_y", "aaab", "The cat sat.", "foobar bazfoo", "é😀 9"];
+        let patterns = ["(a|ab)(c|bcd)", "(?:(a)|b)+", "(a?)+", "(?:a*)*b", "a{1,3}?b", "<.+?>", "\\bcat\\b"];
+        for pattern in PARSER_CORPUS.iter().chain(&patterns) {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let Ok(nfa) = Nfa::compile(&ast) else {
+                continue;
+            };
+            for seed in 0..3 {
+                let obfuscated = Obfuscator::with_seed(seed).obfuscate(ast.clone());
+                let Ok(obfuscated_nfa) = Nfa::compile(&obfuscated) else {
+                    continue;
+                };
+                for haystack in haystacks {
+                    let expected = Matcher::new(&ast).find(haystack).unwrap();
+                    let key = |found: Option<Match>| found.map(|m| (m.span(), m.groups().to_vec()));
+                    assert_eq!(key(nfa.find(haystack)), key(expected.clone()), "{} on {:?}", pattern, haystack);
+                    assert_eq!(key(obfuscated_nfa.find(haystack)), key(expected), "{} on {:?}", pattern, haystack);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_nfa_compile() {
+        let compile = |pattern: &str| Nfa::compile(&Parser::new(pattern).parse().unwrap());
+        assert_eq!(compile("(a)\\1").err(), Some(CompileError::Backreference));
+        assert_eq!(compile("a(?=b)").err(), Some(CompileError::Lookaround));
+        assert_eq!(compile("(?>ab|a)b").err(), Some(CompileError::Atomic));
+        assert_eq!(compile("a*+").err(), Some(CompileError::Atomic));
+
+        // No catastrophic backtracking, where the backtracker gives up
+        let nfa = compile("(a+)+b").unwrap();
+        assert!(!nfa.is_match(&"a".repeat(200)));
+        let found = nfa.find(&format!("{}b", "a".repeat(200))).unwrap();
+        assert_eq!(found.group(1), Some(Span { start: 0, end: 200 }));
+
+        let nfa = compile("(?<year>\\d{4})-(?<month>\\d{2})").unwrap();
+        assert_eq!(nfa.group_count(), 2);
+        let found = nfa.find("on 2024-05").unwrap();
+        assert_eq!(found.span(), Span { start: 3, end: 10 });
+        assert_eq!(found.name("month"), Some(Span { start: 8, end: 10 }));
+    }
+
+    #[test]
+    fn test_language_equivalence() {
+        let check = |a: &str, b: &str| {
+            let a = Parser::new(a).parse().unwrap();
+            let b = Parser::new(b).parse().unwrap();
+            dfa::equivalent(&a, &b).unwrap()
+        };
+        let different = |s: &str| Equivalence::Different(s.to_string());
+
+        assert_eq!(check("a+", "aa*"), Equivalence::Equivalent);
+        assert_eq!(check("(a|b)*", "[ab]*?"), Equivalence::Equivalent);
+        assert_eq!(check("(?i)ab", "[aA][bB]"), Equivalence::Equivalent);
+        assert_eq!(check("^a$", "a"), Equivalence::Equivalent);
+        assert_eq!(check("a\\bb", "[^\\s\\S]"), Equivalence::Equivalent);
+        assert_eq!(check("\\d{2,3}", "[0-9][0-9][0-9]?"), Equivalence::Equivalent);
+
+        assert_eq!(check("a+", "a*"), different(""));
+        assert_eq!(check("[a-c]", "a|b"), different("c"));
+        assert_eq!(check("(?s).", "."), different("\n"));
+        assert_eq!(check("a{2,4}", "a{2,}"), different("aaaaa"));
+        assert_eq!(check("(?m)a$\\n^b", "a\\nb"), Equivalence::Equivalent);
+        assert_eq!(check("a$\\nb", "a\\nb"), different("a\nb"));
+
+        assert_eq!(
+            dfa::equivalent(&Parser::new("(a)\\1").parse().unwrap(), &[]),
+            Err(CompileError::Backreference)
+        );
+    }
+
+    #[test]
+    fn test_obfuscation_preserves_language() {
+        for pattern in PARSER_CORPUS {
+            let ast = Parser::new(pattern).parse().unwrap();
+            for seed in 0..3 {
+                let obfuscated = Obfuscator::with_seed(seed).obfuscate(ast.clone());
+                match dfa::equivalent(&ast, &obfuscated) {
+                    Ok(result) => assert_eq!(
+                        result,
+                        Equivalence::Equivalent,
+                        "{} became {}",
+                        pattern,
+                        Printer::new(false).print(&obfuscated)
+                    ),
+                    Err(_) => continue,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_automaton_obfuscation() {
+        let patterns = [
+            "^abc$", "^[a-z]+@[a-z]+\\.(?:com|org)$", "^a*b*c*$", "^(?:ab|a)(?:c|bc)$", "^\\d{2,3}-\\d{4}$",
+            "^(?i:yes|no)$", "^\\w+$", "^x?y?z?$", "^\\bfoo\\b$", "^a\\bb$",
+        ];
+        for pattern in patterns {
+            let ast = Parser::new(pattern).parse().unwrap();
+            for seed in 0..4 {
+                let mut obfuscator = Obfuscator::builder().clear().automaton(true).seed(seed).build();
+                let rebuilt = obfuscator.obfuscate(ast.clone());
+                let printed = Printer::new(false).print(&rebuilt);
+                assert_eq!(Parser::new(&printed).parse().unwrap(), rebuilt, "{} did not reparse", printed);
+                assert_eq!(dfa::equivalent(&ast, &rebuilt).unwrap(), Equivalence::Equivalent, "{} became {}", pattern, printed);
+                // Searches find the same matches, not just whole strings
+                let mut verifier = Verifier::with_seed(seed);
+                assert!(verifier.verify(&ast, &rebuilt).is_ok(), "{} became {}", pattern, printed);
+
+                // Only the anchors around the rebuilt pattern are kept
+                let mut assertions = 0;
+                ast::walk(&rebuilt, &mut |node| {
+                    if matches!(node, RegexNode::Anchor(_) | RegexNode::WordBoundary) {
+                        assertions += 1;
+                    }
+                });
+                assert_eq!(assertions, 2, "{} kept an assertion in {}", pattern, printed);
+            }
+        }
+
+        let ast = Parser::new("^abc$").parse().unwrap();
+        let rebuilt = Obfuscator::builder().clear().automaton(true).build().obfuscate(ast.clone());
+        for haystack in ["abc", "xabcx", "abcabc", "ab"] {
+            assert_eq!(Matcher::new(&rebuilt).find(haystack), Matcher::new(&ast).find(haystack), "{}", haystack);
+        }
+
+        // Patterns an automaton can't express, or whose matches a search may
+        // find inside the input, are left to the passes
+        let mut obfuscator = Obfuscator::builder().clear().automaton(true).build();
+        for pattern in ["(a)b", "(?<x>a)\\k<x>", "a(?=b)", "abc", "^a|b$", "(?m)^a$"] {
+            let ast = Parser::new(pattern).parse().unwrap();
+            assert_eq!(obfuscator.obfuscate(ast.clone()), ast);
+        }
+    }
+
+    #[test]
+    fn test_redos_analysis() {
+        let analyze = |pattern: &str| redos::analyze(&Parser::new(pattern).parse().unwrap());
+
+        for pattern in ["abc", "[a-z]+@[a-z]+", "(a+b)+", "(?:a|b)*c", "\\d+-\\d+", "(a+)+", ".*x.*"] {
+            assert_eq!(analyze(pattern).complexity, Complexity::Linear, "{}", pattern);
+        }
+        // Atomic bodies are never tried another way
+        for pattern in ["(a+)++b", "(?>a+)+b", "(?>\\w|\\d)+!", "^\\d++\\d+$"] {
+            assert_eq!(analyze(pattern).complexity, Complexity::Linear, "{}", pattern);
+        }
+
+        let cases = [
+            ("(a+)+b", FindingKind::NestedQuantifier, Complexity::Exponential),
+            ("^(\\w+\\s?)*$", FindingKind::NestedQuantifier, Complexity::Exponential),
+            ("(?:\\w|\\d)+!", FindingKind::AmbiguousAlternation, Complexity::Exponential),
+            ("x(ab|a[bc])*y", FindingKind::AmbiguousAlternation, Complexity::Exponential),
+            ("^\\d+\\d+$", FindingKind::OverlappingQuantifiers, Complexity::Polynomial(2)),
+            ("^a*a?a*b*a*$", FindingKind::OverlappingQuantifiers, Complexity::Polynomial(3)),
+        ];
+        for (pattern, kind, complexity) in cases {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let report = redos::analyze(&ast);
+            assert_eq!(report.complexity, complexity, "{}", pattern);
+            assert!(report.findings.iter().any(|finding| finding.kind == kind), "{}: {:?}", pattern, report);
+
+            // The attack makes the backtracker give up
+            let attack = report.findings[0].attack.build(40);
+            let matcher = Matcher::new(&ast).with_step_limit(100_000);
+            let blows_up = matches!(matcher.is_match(&attack), Err(MatchError::StepLimitExceeded));
+            assert_eq!(blows_up, complexity == Complexity::Exponential, "{} on {:?}", pattern, attack);
+            assert!(report.attack().is_some());
+        }
+    }
+
+    #[test]
+    fn test_obfuscator_rejects_redos() {
+        // Splitting the class turns `[\w\d]+` into the exponential `(?:[\w]|[\d])+`
+        let ast = Parser::new("^[\\w\\d]+$").parse().unwrap();
+        let mut risky = Obfuscator::builder().seed(1).build();
+        assert_eq!(redos::analyze(&risky.obfuscate(ast.clone())).complexity, Complexity::Exponential);
+
+        for seed in 0..10 {
+            let mut guarded = Obfuscator::builder().reject_redos(true).seed(seed).build();
+            let obfuscated = guarded.obfuscate(ast.clone());
+            assert_eq!(redos::analyze(&obfuscated).complexity, Complexity::Linear);
+        }
+    }
+
+    #[test]
+    fn test_obfuscation_budget() {
+        let printer = Printer::new(false);
+        for pattern in ["[abc]", "hello", "(a)\\1[x-z]+", "^\\d{2,}.$"] {
+            let ast = Parser::new(pattern).parse().unwrap();
+            let input_length = printer.print(&ast).chars().count();
+            for seed in 0..20 {
+                let budget = ObfuscationBudget::max_growth(2.0).with_max_length(40);
+                let mut obfuscator = Obfuscator::builder()
+                    .weight("lookaround-noise", 0.5)
+                    .budget(budget)
+                    .seed(seed)
+                    .build();
+                let obfuscated = obfuscator.obfuscate(ast.clone());
+                let length = printer.print(&obfuscated).chars().count();
+                assert!(length <= (2 * input_length).min(40), "{} -> {}", pattern, length);
+            }
+        }
+
+        // Stacked passes still get to run where there's room
+        let ast = Parser::new("abc").parse().unwrap();
+        let mut obfuscator = Obfuscator::builder().budget(ObfuscationBudget::max_length(100)).seed(3).build();
+        assert_ne!(printer.print(&obfuscator.obfuscate(ast.clone())), "abc");
+
+        // An input over the limit comes back unobfuscated
+        let mut obfuscator = Obfuscator::builder().budget(ObfuscationBudget::max_length(1)).seed(3).build();
+        assert_eq!(obfuscator.obfuscate(ast.clone()), ast);
+    }
+
+    #[test]
+    fn test_intensity_presets() {
+        let printer = Printer::new(false);
+        let ast = Parser::new("(a+)b{2}[c-f].\\1").parse().unwrap();
+        let total_length = |intensity: Intensity| -> usize {
+            (0..10)
+                .map(|seed| {
+                    let mut obfuscator = Obfuscator::builder().intensity(intensity).seed(seed).build();
+                    let obfuscated = obfuscator.obfuscate(ast.clone());
+                    Verifier::with_seed(seed).verify(&ast, &obfuscated).unwrap();
+                    printer.print(&obfuscated).len()
+                })
+                .sum()
+        };
+
+        let lengths = [Intensity::Light, Intensity::Medium, Intensity::Heavy, Intensity::Paranoid]
+            .map(total_length);
+        assert!(lengths.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", lengths);
+
+        // Medium is the default pipeline
+        let mut medium = Obfuscator::builder().intensity(Intensity::Medium).seed(5).build();
+        assert_eq!(medium.obfuscate(ast.clone()), Obfuscator::with_seed(5).obfuscate(ast.clone()));
+        assert_eq!("Paranoid".parse::<Intensity>(), Ok(Intensity::Paranoid));
+    }
+
+    #[test]
+    fn test_stats() {
+        let printer = Printer::new(false);
+        let ast = Parser::new("(?<x>a|bc)+(?:d(e))?").parse().unwrap();
+        let stats = Stats::of(&ast, &printer).unwrap();
+        assert_eq!(stats.node_count, 11);
+        assert_eq!(stats.max_depth, 4);
+        assert_eq!(stats.capture_groups, 2);
+        assert_eq!(stats.printed_length, 20);
+        assert_eq!(stats.alternation_branches, 2);
+
+        // The estimate is exact wherever an NFA can be built
+        for pattern in PARSER_CORPUS.iter().chain(&["a{2,4}", "(a|b|c){3,}?", "x{0}"]) {
+            let ast = Parser::new(pattern).parse().unwrap();
+            if let Ok(nfa) = Nfa::compile(&ast) {
+                assert_eq!(Stats::of(&ast, &printer).unwrap().nfa_states, nfa.states().len(), "{}", pattern);
+            }
+        }
+
+        let before = Stats::of(&Parser::new("[abc]").parse().unwrap(), &printer).unwrap();
+        let after = Stats::of(&Parser::new("(?:[a]|[b]|[c])").parse().unwrap(), &printer).unwrap();
+        assert_eq!(before.growth(&after), 3.0);
+
+        // Too many states to count saturates rather than overflowing
+        let ast = Parser::new("((a{1000000000}){1000000000}){1000000000}").parse().unwrap();
+        assert_eq!(Stats::of(&ast, &printer).unwrap().nfa_states, usize::MAX);
+
+        // Measuring for a dialect that can't print the pattern is an error
+        let re2 = Printer::new(false).with_dialect(Dialect::Re2);
+        let err = Stats::of(&Parser::new("a(?=b)").parse().unwrap(), &re2).unwrap_err();
+        assert_eq!(err, PrintError { feature: Feature::Lookahead, dialect: Dialect::Re2 });
+    }
 } 
\ No newline at end of file
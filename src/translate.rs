@@ -0,0 +1,412 @@
+//! Rewrites a pattern so another dialect can print it.
+//!
+//! Constructs the target lacks are lowered where an equivalent exists:
+//!
+//! - `\d`, `\w` and `\s` become the classes they stand for;
+//! - named groups become numbered ones, and named backreferences follow;
+//! - non-capturing groups are dropped, or become capturing where they are
+//!   still needed for grouping;
+//! - inline flags are spelled out, with `(?i)` turned into case classes;
+//! - possessive quantifiers become atomic groups where only those exist;
+//! - chars past U+FFFF are taken out of .NET classes into alternatives.
+//!
+//! Whatever is left, such as backreferences for RE2 or lookarounds for
+//! POSIX, is reported rather than approximated.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::ast::{
+    self, CharacterTypeKind, ClassItem, GroupKind, BackreferenceKind, LookaroundKind,
+    QuantifierMode, RegexFlags, RegexNode,
+};
+use crate::dialect::{Dialect, Feature};
+use crate::matcher;
+use crate::printer::Printer;
+
+/// The constructs of a pattern the target dialect has no way to express.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Untranslatable {
+    pub dialect: Dialect,
+    /// Each construct once, in the order they were found
+    pub features: Vec<Feature>,
+}
+
+impl fmt::Display for Untranslatable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self.features.iter().map(|feature| feature.to_string()).collect();
+        let list = match names.split_last() {
+            Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+            _ => names.concat(),
+        };
+        write!(f, "{} can't express {}", self.dialect, list)
+    }
+}
+
+impl std::error::Error for Untranslatable {}
+
+/// Lowers what `target` can't express into what it can, so the result
+/// prints in that dialect.
+pub fn translate(nodes: &[RegexNode], target: Dialect) -> Result<Vec<RegexNode>, Untranslatable> {
+    let mut names = HashMap::new();
+    let mut group_count = 0;
+    ast::walk(nodes, &mut |node| {
+        if let RegexNode::Group(GroupKind::Capturing(name), _) = node {
+            group_count += 1;
+            if let Some(name) = name {
+                names.entry(name.clone()).or_insert(group_count);
+            }
+        }
+    });
+
+    let mut translator = Translator {
+        target,
+        names,
+        features: Vec::new(),
+    };
+    let translated = translator.sequence(nodes, &RegexFlags::new());
+
+    // Anything the lowering missed still shows up when printing
+    if translator.features.is_empty() {
+        if let Err(err) = Printer::new(false).with_dialect(target).try_print(&translated) {
+            translator.report(err.feature);
+        }
+    }
+
+    if translator.features.is_empty() {
+        Ok(translated)
+    } else {
+        Err(Untranslatable {
+            dialect: target,
+            features: translator.features,
+        })
+    }
+}
+
+struct Translator {
+    target: Dialect,
+    // Group numbers of named groups, for rewriting named backreferences
+    names: HashMap<String, usize>,
+    features: Vec<Feature>,
+}
+
+impl Translator {
+    fn report(&mut self, feature: Feature) {
+        if !self.features.contains(&feature) {
+            self.features.push(feature);
+        }
+    }
+
+    fn supports(&self, feature: Feature) -> bool {
+        self.target.supports(feature)
+    }
+
+    fn sequence(&mut self, nodes: &[RegexNode], flags: &RegexFlags) -> Vec<RegexNode> {
+        nodes.iter().flat_map(|node| self.node(node, flags)).collect()
+    }
+
+    /// Translates a node into the nodes that replace it in its sequence.
+    fn node(&mut self, node: &RegexNode, flags: &RegexFlags) -> Vec<RegexNode> {
+        let lower_flags = !self.supports(Feature::InlineFlags);
+        let translated = match node {
+            RegexNode::Literal(c) if lower_flags && flags.case_insensitive => case_class(*c),
+            RegexNode::CharacterType(CharacterTypeKind::EscapedChar(esc))
+                if lower_flags && flags.case_insensitive =>
+            {
+                match esc.to_char() {
+                    Some(c) => case_class(c),
+                    None => node.clone(),
+                }
+            }
+            RegexNode::CharacterType(kind) if !self.supports(Feature::CharacterType) => {
+                match character_type_items(kind) {
+                    Some((negated, items)) => RegexNode::new_class(items, negated),
+                    None => node.clone(),
+                }
+            }
+            RegexNode::CharacterClass { negated, items } => self.class(items, *negated, flags),
+            // POSIX `.` matches newlines as well
+            RegexNode::Dot if self.target == Dialect::PosixEre && !flags.dot_all => {
+                RegexNode::new_char_class(vec!['\n'], true)
+            }
+            RegexNode::Anchor(_) if lower_flags && flags.multiline => {
+                self.report(Feature::InlineFlags);
+                node.clone()
+            }
+            RegexNode::WordBoundary if !self.supports(Feature::WordBoundary) => {
+                self.report(Feature::WordBoundary);
+                node.clone()
+            }
+            RegexNode::UnicodeCategory { .. } if !self.supports(Feature::UnicodeCategory) => {
+                self.report(Feature::UnicodeCategory);
+                node.clone()
+            }
+            RegexNode::Quantified { node: inner, quantifier } => {
+                let mut inner = self.node(inner, flags);
+                let inner = match inner.len() {
+                    // Repeating nothing matches nothing
+                    0 => return Vec::new(),
+                    1 if !matches!(inner[0], RegexNode::Quantified { .. } | RegexNode::Alternation(_)) => {
+                        inner.remove(0)
+                    }
+                    _ => self.group(inner),
+                };
+                match quantifier.mode() {
+                    QuantifierMode::Lazy if !self.supports(Feature::LazyQuantifier) => {
+                        self.report(Feature::LazyQuantifier);
+                    }
+                    // `a++` → `(?>a+)`
+                    QuantifierMode::Possessive
+                        if !self.supports(Feature::PossessiveQuantifier)
+                            && self.supports(Feature::AtomicGroup) =>
+                    {
+                        let greedy = inner.with_quantifier(quantifier.clone().with_mode(QuantifierMode::Greedy));
+                        return vec![RegexNode::new_group(GroupKind::Atomic, vec![greedy])];
+                    }
+                    QuantifierMode::Possessive if !self.supports(Feature::PossessiveQuantifier) => {
+                        self.report(Feature::PossessiveQuantifier);
+                    }
+                    _ => {}
+                }
+                inner.with_quantifier(quantifier.clone())
+            }
+            RegexNode::Group(kind, nodes) => {
+                let nodes = self.sequence(nodes, flags);
+                match kind {
+                    GroupKind::Capturing(Some(_)) if !self.supports(Feature::NamedGroup) => {
+                        RegexNode::new_group(GroupKind::Capturing(None), nodes)
+                    }
+                    GroupKind::NonCapturing if !self.supports(Feature::NonCapturingGroup) => {
+                        return self.ungroup(nodes);
+                    }
+                    GroupKind::Atomic if !self.supports(Feature::AtomicGroup) => {
+                        self.report(Feature::AtomicGroup);
+                        RegexNode::new_group(GroupKind::Atomic, nodes)
+                    }
+                    _ => RegexNode::new_group(kind.clone(), nodes),
+                }
+            }
+            RegexNode::Alternation(alternatives) => RegexNode::new_alternation(
+                alternatives.iter().map(|alt| self.sequence(alt, flags)).collect(),
+            ),
+            RegexNode::Backreference(kind) => {
+                if !self.supports(Feature::Backreference) {
+                    self.report(Feature::Backreference);
+                } else if lower_flags && flags.case_insensitive {
+                    // Case classes can't say "the same text, in any case"
+                    self.report(Feature::InlineFlags);
+                }
+                match kind {
+                    BackreferenceKind::NameBased(name) if !self.supports(Feature::NamedGroup) => {
+                        match self.names.get(name) {
+                            Some(&index) => RegexNode::new_backreference(BackreferenceKind::NumberBased(index)),
+                            None => node.clone(),
+                        }
+                    }
+                    _ => node.clone(),
+                }
+            }
+            RegexNode::Lookaround(kind, nodes) => {
+                let nodes = self.sequence(nodes, flags);
+                let supported = match kind {
+                    LookaroundKind::PositiveLookahead | LookaroundKind::NegativeLookahead => {
+                        if self.supports(Feature::Lookahead) { Ok(()) } else { Err(Feature::Lookahead) }
+                    }
+                    _ => self.target.check_lookbehind(&nodes),
+                };
+                if let Err(feature) = supported {
+                    self.report(feature);
+                }
+                RegexNode::new_lookaround(kind.clone(), nodes)
+            }
+            RegexNode::FlagSet(change, scope, nodes) => {
+                let nodes = self.sequence(nodes, &flags.apply(change));
+                if lower_flags {
+                    return self.ungroup(nodes);
+                }
+                RegexNode::FlagSet(change.clone(), scope.clone(), nodes)
+            }
+            _ => node.clone(),
+        };
+        vec![translated]
+    }
+
+    fn class(&mut self, items: &[ClassItem], negated: bool, flags: &RegexFlags) -> RegexNode {
+        let mut negated = negated;
+        let mut items = items.to_vec();
+
+        if !self.supports(Feature::CharacterType) {
+            // A lone negated type flips the class; among other items it can't
+            // be spelled without subtracting
+            if let [ClassItem::CharacterType(kind)] = items.as_slice() {
+                if let Some((type_negated, type_items)) = character_type_items(kind) {
+                    negated ^= type_negated;
+                    items = type_items;
+                }
+            }
+            let mut lowered = Vec::new();
+            for item in items {
+                match &item {
+                    ClassItem::CharacterType(kind) => match character_type_items(kind) {
+                        Some((false, type_items)) => lowered.extend(type_items),
+                        Some((true, _)) => {
+                            self.report(Feature::CharacterType);
+                            lowered.push(item);
+                        }
+                        None => lowered.push(item),
+                    },
+                    _ => lowered.push(item),
+                }
+            }
+            items = lowered;
+        }
+
+        if items.iter().any(|item| matches!(item, ClassItem::UnicodeCategory { .. }))
+            && !self.supports(Feature::UnicodeCategory)
+        {
+            self.report(Feature::UnicodeCategory);
+        }
+
+        if flags.case_insensitive && !self.supports(Feature::InlineFlags) {
+            let extra = case_variants_outside(&items);
+            items.extend(class_items(extra));
+        }
+
+        if !self.supports(Feature::AstralCharInClass) {
+            return self.split_astral(items, negated);
+        }
+        RegexNode::new_class(items, negated)
+    }
+
+    /// Takes chars past U+FFFF out of a class into alternatives of their own.
+    fn split_astral(&mut self, items: Vec<ClassItem>, negated: bool) -> RegexNode {
+        let astral = |c: char| c as u32 > 0xFFFF;
+        let (astral_items, rest): (Vec<_>, Vec<_>) = items.into_iter().partition(|item| match item {
+            ClassItem::Range { end, .. } => astral(*end),
+            _ => item.as_char().is_some_and(astral),
+        });
+        if astral_items.is_empty() {
+            return RegexNode::new_class(rest, negated);
+        }
+
+        let chars: Vec<char> = astral_items.iter().filter_map(ClassItem::as_char).collect();
+        // Ranges and negations would need surrogate arithmetic
+        if negated || chars.len() < astral_items.len() {
+            self.report(Feature::AstralCharInClass);
+            return RegexNode::new_class(rest.into_iter().chain(astral_items).collect(), negated);
+        }
+
+        let mut alternatives = Vec::new();
+        match rest.as_slice() {
+            [] => {}
+            [item] if item.as_char().is_some() => {
+                alternatives.push(vec![RegexNode::Literal(item.as_char().unwrap())])
+            }
+            _ => alternatives.push(vec![RegexNode::new_class(rest, false)]),
+        }
+        alternatives.extend(chars.into_iter().map(|c| vec![RegexNode::Literal(c)]));
+        match alternatives.len() {
+            1 => alternatives.remove(0).remove(0),
+            _ => self.group(vec![RegexNode::new_alternation(alternatives)]),
+        }
+    }
+
+    /// The nodes of a group whose own parentheses are going away, spliced
+    /// into the enclosing sequence unless an alternation still needs them.
+    fn ungroup(&self, nodes: Vec<RegexNode>) -> Vec<RegexNode> {
+        if nodes.iter().any(|node| matches!(node, RegexNode::Alternation(_))) {
+            vec![self.group(nodes)]
+        } else {
+            nodes
+        }
+    }
+
+    /// Groups nodes, capturing only where the target has nothing else.
+    fn group(&self, nodes: Vec<RegexNode>) -> RegexNode {
+        let kind = if self.supports(Feature::NonCapturingGroup) {
+            GroupKind::NonCapturing
+        } else {
+            GroupKind::Capturing(None)
+        };
+        RegexNode::new_group(kind, nodes)
+    }
+}
+
+/// The char as a class of its case forms, or the char alone if it has none.
+fn case_class(c: char) -> RegexNode {
+    let mut chars = Vec::new();
+    for variant in matcher::case_variants(c) {
+        if !chars.contains(&variant) {
+            chars.push(variant);
+        }
+    }
+    if chars.len() == 1 {
+        RegexNode::Literal(c)
+    } else {
+        RegexNode::new_char_class(chars, false)
+    }
+}
+
+/// The case forms of the class's chars that the class doesn't already have.
+fn case_variants_outside(items: &[ClassItem]) -> BTreeSet<char> {
+    let covers = |c: char| {
+        items.iter().any(|item| match item {
+            ClassItem::Range { start, end } => (*start..=*end).contains(&c),
+            _ => item.as_char() == Some(c),
+        })
+    };
+    let mut extra = BTreeSet::new();
+    for item in items {
+        let chars: Vec<char> = match item {
+            ClassItem::Range { start, end } => (*start..=*end).collect(),
+            _ => item.as_char().into_iter().collect(),
+        };
+        for c in chars {
+            extra.extend(matcher::case_variants(c).filter(|&variant| !covers(variant)));
+        }
+    }
+    extra
+}
+
+/// The class a `\d`, `\w` or `\s` stands for, as whether it is negated and
+/// its items. `None` for escaped chars, which aren't types at all.
+fn character_type_items(kind: &CharacterTypeKind) -> Option<(bool, Vec<ClassItem>)> {
+    let items = |kind: &CharacterTypeKind| match kind {
+        CharacterTypeKind::Digit | CharacterTypeKind::NotDigit => vec![ClassItem::new_range('0', '9')],
+        CharacterTypeKind::Word | CharacterTypeKind::NotWord => vec![
+            ClassItem::new_range('a', 'z'),
+            ClassItem::new_range('A', 'Z'),
+            ClassItem::new_range('0', '9'),
+            ClassItem::Char('_'),
+        ],
+        // Everything `char::is_whitespace` takes, which ends at U+3000
+        _ => class_items(('\0'..='\u{3000}').filter(|c| c.is_whitespace())),
+    };
+    match kind {
+        CharacterTypeKind::Digit | CharacterTypeKind::Word | CharacterTypeKind::Whitespace => {
+            Some((false, items(kind)))
+        }
+        CharacterTypeKind::NotDigit | CharacterTypeKind::NotWord | CharacterTypeKind::NotWhitespace => {
+            Some((true, items(kind)))
+        }
+        CharacterTypeKind::EscapedChar(_) => None,
+    }
+}
+
+/// Class items for ascending chars, with runs of three or more as ranges.
+fn class_items(chars: impl IntoIterator<Item = char>) -> Vec<ClassItem> {
+    let mut runs: Vec<(char, char)> = Vec::new();
+    for c in chars {
+        match runs.last_mut() {
+            Some((_, end)) if (*end as u32) + 1 == c as u32 => *end = c,
+            _ => runs.push((c, c)),
+        }
+    }
+    runs.into_iter()
+        .flat_map(|(start, end)| match end as u32 - start as u32 {
+            0 => vec![ClassItem::Char(start)],
+            1 => vec![ClassItem::Char(start), ClassItem::Char(end)],
+            _ => vec![ClassItem::new_range(start, end)],
+        })
+        .collect()
+}
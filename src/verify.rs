@@ -0,0 +1,148 @@
+//! Checks that an obfuscated pattern still behaves like the original by
+//! running both through the [`Matcher`] on many generated inputs.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::ast::RegexNode;
+use crate::generate::Generator;
+use crate::matcher::{Match, Matcher};
+
+// Steps each matcher may take per input, to survive patterns that backtrack badly
+const STEP_LIMIT: usize = 100_000;
+
+pub struct Verifier {
+    generator: Generator,
+    samples: usize,
+}
+
+/// An input on which the two patterns disagree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub input: String,
+    pub original: Option<Match>,
+    pub obfuscated: Option<Match>,
+}
+
+/// How many inputs a successful check ran.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Report {
+    pub checked: usize,
+    /// Inputs dropped because a pattern backtracked past the step limit
+    pub skipped: usize,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |found: &Option<Match>| match found {
+            None => "no match".to_string(),
+            Some(found) => {
+                let span = found.span();
+                let groups: Vec<String> = found
+                    .groups()
+                    .iter()
+                    .map(|group| match group {
+                        Some(span) => format!("{}..{}", span.start, span.end),
+                        None => "-".to_string(),
+                    })
+                    .collect();
+                format!("match {}..{} with groups [{}]", span.start, span.end, groups.join(", "))
+            }
+        };
+        write!(
+            f,
+            "on input {:?} the original gives {} but the obfuscated pattern gives {}",
+            self.input,
+            describe(&self.original),
+            describe(&self.obfuscated)
+        )
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+impl Default for Verifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Verifier {
+    pub fn new() -> Self {
+        Verifier {
+            generator: Generator::new(),
+            samples: 200,
+        }
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Verifier {
+            generator: Generator::with_seed(seed),
+            samples: 200,
+        }
+    }
+
+    /// How many rounds of inputs to try. Each round runs one string sampled
+    /// to match, one near-miss and one random string.
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Runs both patterns on generated inputs and compares whether they
+    /// match, where, and what each capturing group holds.
+    pub fn verify(
+        &mut self,
+        original: &[RegexNode],
+        obfuscated: &[RegexNode],
+    ) -> Result<Report, Box<Mismatch>> {
+        let original_matcher = Matcher::new(original).with_step_limit(STEP_LIMIT);
+        let obfuscated_matcher = Matcher::new(obfuscated).with_step_limit(STEP_LIMIT);
+
+        let mut report = Report { checked: 0, skipped: 0 };
+        let mut seen = HashSet::new();
+        for round in 0..self.samples {
+            let mut sample = self.generator.sample(original);
+            // Every other sample is surrounded by random text, so the
+            // patterns also have to agree on where the match starts
+            if round % 2 == 1 {
+                sample = format!(
+                    "{}{}{}",
+                    self.generator.random_string(original),
+                    sample,
+                    self.generator.random_string(original)
+                );
+            }
+            let inputs = [
+                sample,
+                self.generator.near_miss(original),
+                self.generator.random_string(original),
+            ];
+
+            for input in inputs {
+                if !seen.insert(input.clone()) {
+                    continue;
+                }
+
+                let (Ok(expected), Ok(actual)) =
+                    (original_matcher.find(&input), obfuscated_matcher.find(&input))
+                else {
+                    report.skipped += 1;
+                    continue;
+                };
+                let key = |found: &Option<Match>| {
+                    found.as_ref().map(|found| (found.span(), found.groups().to_vec()))
+                };
+                if key(&expected) != key(&actual) {
+                    return Err(Box::new(Mismatch {
+                        input,
+                        original: expected,
+                        obfuscated: actual,
+                    }));
+                }
+                report.checked += 1;
+            }
+        }
+        Ok(report)
+    }
+}